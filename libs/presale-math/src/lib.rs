@@ -0,0 +1,286 @@
+//! Pricing and reward math of the presale program.
+//!
+//! Pure `no_std` functions shared by the on-chain program and off-chain
+//! clients, so a client quote matches what the program charges to the lamport.
+//! Every function returns `None` on overflow or division by zero and states
+//! which way it rounds.
+#![no_std]
+
+pub const BASIS_POINTS: u64 = 10_000;
+
+/// Rounding direction of a division.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+/// A priced purchase.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Quote {
+    pub token_amount: u64, // tokens bought
+    pub sol_amount: u64, // lamports paid
+    pub token_price: u64, // token price after the trade
+}
+
+/// One whole token in base units.
+pub fn pow10(decimals: u8) -> Option<u64> {
+    10u64.checked_pow(decimals.into())
+}
+
+/// `a * b / c` computed in 128 bits.
+pub fn mul_div(a: u64, b: u64, c: u64, rounding: Rounding) -> Option<u64> {
+    let product = (a as u128).checked_mul(b as u128)?;
+    let c = c as u128;
+    if c == 0 {
+        return None;
+    }
+    let result = match rounding {
+        Rounding::Down => product / c,
+        Rounding::Up => product.div_ceil(c),
+    };
+    u64::try_from(result).ok()
+}
+
+/// `bps` basis points of `amount`, rounded down.
+pub fn bps_of(amount: u64, bps: u64) -> Option<u64> {
+    mul_div(amount, bps, BASIS_POINTS, Rounding::Down)
+}
+
+/// Tokens bought with `lamports` at `price` lamports per whole token, rounded down.
+pub fn tokens_for_lamports(lamports: u64, price: u64, decimals: u8) -> Option<u64> {
+    mul_div(lamports, pow10(decimals)?, price, Rounding::Down)
+}
+
+/// Lamports paid for `tokens` at `price` lamports per whole token.
+pub fn lamports_for_tokens(tokens: u64, price: u64, decimals: u8, rounding: Rounding) -> Option<u64> {
+    mul_div(tokens, price, pow10(decimals)?, rounding)
+}
+
+/// Price after buying `tokens` on the curve starting at `price` and moving by
+/// `rate` lamports per whole token: `price + rate * tokens / 2`, rounded down.
+/// The whole purchase is sold at this price, the area under the curve.
+pub fn curve_price(price: u64, rate: u64, tokens: u64, decimals: u8) -> Option<u64> {
+    let bump = mul_div(rate, tokens, pow10(decimals)?, Rounding::Down)? / 2;
+    price.checked_add(bump)
+}
+
+/// Tokens bought with `lamports` on the curve, rounded down.
+///
+/// Inverts `lamports = (price + rate * tokens / unit / 2) * tokens / unit`.
+pub fn curve_tokens_for_lamports(price: u64, rate: u64, lamports: u64, decimals: u8) -> Option<u64> {
+    if rate == 0 {
+        return tokens_for_lamports(lamports, price, decimals);
+    }
+    let unit = pow10(decimals)? as u128;
+    let (price, rate) = (price as u128, rate as u128);
+
+    let root = isqrt(price.checked_mul(price)?.checked_add(rate.checked_mul(2)?.checked_mul(lamports as u128)?)?)?;
+    let tokens = unit.checked_mul(root.checked_sub(price)?)? / rate;
+    u64::try_from(tokens).ok()
+}
+
+/// Spends exactly `lamports` on the curve, the token amount is rounded down.
+pub fn quote_exact_in(price: u64, rate: u64, lamports: u64, decimals: u8) -> Option<Quote> {
+    let token_amount = curve_tokens_for_lamports(price, rate, lamports, decimals)?;
+    Some(Quote {
+        token_amount,
+        sol_amount: lamports,
+        token_price: curve_price(price, rate, token_amount, decimals)?,
+    })
+}
+
+/// Buys exactly `tokens` on the curve, the lamport cost is rounded up.
+pub fn quote_exact_out(price: u64, rate: u64, tokens: u64, decimals: u8) -> Option<Quote> {
+    let token_price = curve_price(price, rate, tokens, decimals)?;
+    Some(Quote {
+        token_amount: tokens,
+        sol_amount: lamports_for_tokens(tokens, token_price, decimals, Rounding::Up)?,
+        token_price,
+    })
+}
+
+/// Payout of a stake in percent of the staked amount, `None` for an unsupported period.
+pub fn stake_payout_percent(staked_period: u8) -> Option<u64> {
+    match staked_period {
+        3 => Some(105),
+        6 => Some(110),
+        9 => Some(115),
+        12 => Some(120),
+        _ => None,
+    }
+}
+
+/// Staked tokens plus the yield paid at the end of the period, rounded down.
+pub fn stake_payout(amount: u64, payout_percent: u64) -> Option<u64> {
+    mul_div(amount, payout_percent, 100, Rounding::Down)
+}
+
+/// Integer square root, rounded down.
+pub fn isqrt(value: u128) -> Option<u128> {
+    if value < 2 {
+        return Some(value);
+    }
+    // the first newton step from `value`, without overflowing `value + 1`
+    let mut x = value;
+    let mut y = value / 2 + (value & 1);
+    while y < x {
+        x = y;
+        y = x.checked_add(value / x)? / 2;
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_rounds_both_ways() {
+        assert_eq!(mul_div(10, 10, 3, Rounding::Down), Some(33));
+        assert_eq!(mul_div(10, 10, 3, Rounding::Up), Some(34));
+        // exact divisions don't round up
+        assert_eq!(mul_div(10, 9, 3, Rounding::Up), Some(30));
+        assert_eq!(mul_div(0, 7, 3, Rounding::Up), Some(0));
+        assert_eq!(mul_div(1, 1, u64::MAX, Rounding::Down), Some(0));
+        assert_eq!(mul_div(1, 1, u64::MAX, Rounding::Up), Some(1));
+    }
+
+    #[test]
+    fn mul_div_keeps_the_intermediate_product_in_128_bits() {
+        assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX, Rounding::Down), Some(u64::MAX));
+        assert_eq!(mul_div(u64::MAX, 2, 4, Rounding::Up), Some(u64::MAX / 2 + 1));
+    }
+
+    #[test]
+    fn mul_div_overflow_and_division_by_zero() {
+        assert_eq!(mul_div(1, 1, 0, Rounding::Down), None);
+        assert_eq!(mul_div(0, 0, 0, Rounding::Up), None);
+        assert_eq!(mul_div(u64::MAX, 2, 1, Rounding::Down), None);
+        assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX - 1, Rounding::Down), None);
+    }
+
+    #[test]
+    fn bps_of_rounds_down() {
+        assert_eq!(bps_of(1_000, 250), Some(25));
+        assert_eq!(bps_of(39, 250), Some(0));
+        assert_eq!(bps_of(u64::MAX, BASIS_POINTS), Some(u64::MAX));
+        assert_eq!(bps_of(u64::MAX, BASIS_POINTS + 1), None);
+    }
+
+    #[test]
+    fn pow10_overflow() {
+        assert_eq!(pow10(0), Some(1));
+        assert_eq!(pow10(19), Some(10_000_000_000_000_000_000));
+        assert_eq!(pow10(20), None);
+        assert_eq!(tokens_for_lamports(1, 1, 20), None);
+    }
+
+    #[test]
+    fn isqrt_boundaries() {
+        assert_eq!(isqrt(0), Some(0));
+        assert_eq!(isqrt(1), Some(1));
+        assert_eq!(isqrt(2), Some(1));
+        assert_eq!(isqrt(3), Some(1));
+        assert_eq!(isqrt(4), Some(2));
+        assert_eq!(isqrt(99), Some(9));
+        assert_eq!(isqrt(100), Some(10));
+        let max_root = u64::MAX as u128;
+        assert_eq!(isqrt(max_root * max_root), Some(max_root));
+        assert_eq!(isqrt(max_root * max_root - 1), Some(max_root - 1));
+        assert_eq!(isqrt(u128::MAX), Some(max_root));
+    }
+
+    #[test]
+    fn isqrt_rounds_down() {
+        for value in (0..100_000u128).chain((u128::MAX - 1_000)..=u128::MAX) {
+            let root = isqrt(value).unwrap();
+            assert!(root * root <= value);
+            assert!((root + 1).checked_mul(root + 1).is_none_or(|square| square > value));
+        }
+    }
+
+    #[test]
+    fn curve_without_rate_is_a_flat_price() {
+        assert_eq!(curve_price(100_000, 0, 5_000_000_000, 9), Some(100_000));
+        assert_eq!(curve_tokens_for_lamports(100_000, 0, 100_000_000, 9), tokens_for_lamports(100_000_000, 100_000, 9));
+        assert_eq!(curve_tokens_for_lamports(0, 0, 1, 9), None);
+    }
+
+    #[test]
+    fn curve_tokens_invert_the_curve_cost() {
+        let decimals = 6;
+        for &(price, rate) in &[(100_000, 5_000), (1, 1), (1_000_000_000, 3), (7, 1_000_000_000)] {
+            for &lamports in &[1, 999, 100_000_000, 123_456_789_012, u32::MAX as u64] {
+                let tokens = curve_tokens_for_lamports(price, rate, lamports, decimals).unwrap();
+                // the exact area under the curve, `(price + rate * tokens / unit / 2) * tokens / unit`, compared to the lamports
+                let unit = pow10(decimals).unwrap() as u128;
+                let twice_cost = |tokens: u64| (2 * price as u128 * unit + rate as u128 * tokens as u128) * tokens as u128;
+                let twice_lamports = 2 * lamports as u128 * unit * unit;
+                assert!(twice_cost(tokens) <= twice_lamports, "{price} {rate} {lamports}");
+                // the square root is rounded down, the buyer gets at most `unit / rate + 1` base units less
+                let shortfall = unit as u64 / rate + 2;
+                assert!(twice_cost(tokens + shortfall) >= twice_lamports, "{price} {rate} {lamports}");
+            }
+        }
+    }
+
+    #[test]
+    fn curve_overflow() {
+        assert_eq!(curve_price(u64::MAX, 2, 1_000_000_000, 9), None);
+        assert_eq!(curve_tokens_for_lamports(u64::MAX, u64::MAX, u64::MAX, 9), None);
+        assert_eq!(curve_tokens_for_lamports(100_000, 5_000, 1, 20), None);
+    }
+
+    #[test]
+    fn quote_exact_in_spends_the_lamports() {
+        // 0.1 sol at 100_000 lamports per token with 9 decimals
+        let quote = quote_exact_in(100_000, 0, 100_000_000, 9).unwrap();
+        assert_eq!(quote, Quote { token_amount: 1_000_000_000_000, sol_amount: 100_000_000, token_price: 100_000 });
+
+        let quote = quote_exact_in(100_000, 5_000, 100_000_000, 9).unwrap();
+        assert_eq!(quote.sol_amount, 100_000_000);
+        assert!(quote.token_amount < 1_000_000_000_000);
+        assert!(quote.token_price > 100_000);
+        assert_eq!(quote.token_price, curve_price(100_000, 5_000, quote.token_amount, 9).unwrap());
+    }
+
+    #[test]
+    fn quote_exact_out_rounds_the_cost_up() {
+        let quote = quote_exact_out(100_000, 0, 100_000_000_000, 9).unwrap();
+        assert_eq!(quote, Quote { token_amount: 100_000_000_000, sol_amount: 10_000_000, token_price: 100_000 });
+
+        // a single base unit still costs a lamport
+        let quote = quote_exact_out(3, 0, 1, 9).unwrap();
+        assert_eq!(quote.sol_amount, 1);
+        assert_eq!(quote_exact_out(3, 0, 0, 9).unwrap().sol_amount, 0);
+    }
+
+    #[test]
+    fn quotes_agree_both_ways() {
+        let (price, rate, decimals) = (100_000, 5_000, 9);
+        let exact_in = quote_exact_in(price, rate, 2_000_000_000, decimals).unwrap();
+        let exact_out = quote_exact_out(price, rate, exact_in.token_amount, decimals).unwrap();
+        assert_eq!(exact_out.token_price, exact_in.token_price);
+        assert!(exact_out.sol_amount <= exact_in.sol_amount);
+    }
+
+    #[test]
+    fn quote_overflow() {
+        assert_eq!(quote_exact_in(0, 0, 1, 9), None);
+        assert_eq!(quote_exact_in(100_000, 0, u64::MAX, 9), None);
+        assert_eq!(quote_exact_out(u64::MAX, 0, u64::MAX, 0), None);
+        assert_eq!(quote_exact_out(100_000, 0, 1, 20), None);
+    }
+
+    #[test]
+    fn stake_payouts() {
+        assert_eq!(stake_payout_percent(3), Some(105));
+        assert_eq!(stake_payout_percent(12), Some(120));
+        assert_eq!(stake_payout_percent(0), None);
+        assert_eq!(stake_payout_percent(4), None);
+        assert_eq!(stake_payout(1_000, 105), Some(1_050));
+        assert_eq!(stake_payout(19, 105), Some(19));
+        assert_eq!(stake_payout(u64::MAX, 120), None);
+    }
+}
@@ -5,5 +5,24 @@ pub const VAULT_SEED: &[u8] = b"VAULT_SEED";
 pub const TOKEN_VAULT_SEED: &[u8] = b"TOKEN_VAULT_SEED";
 pub const PRESALE_SEED: &[u8] = b"PRESALE_SEED";
 pub const USER_INFO_SEED: &[u8] = b"USER_INFO_SEED";
+pub const TRANCHE_VOTE_SEED: &[u8] = b"TRANCHE_VOTE_SEED";
+pub const PLATFORM_SEED: &[u8] = b"PLATFORM_SEED";
+pub const FEE_VAULT_SEED: &[u8] = b"FEE_VAULT_SEED";
+pub const REFERRER_SEED: &[u8] = b"REFERRER_SEED";
+pub const VOUCHER_NONCE_SEED: &[u8] = b"VOUCHER_NONCE_SEED";
+pub const GATE_ALLOCATION_SEED: &[u8] = b"GATE_ALLOCATION_SEED";
 
+pub const MAX_MILESTONES: usize = 5;
+pub const MAX_RECIPIENTS: usize = 5;
+pub const MAX_BONUS_WINDOWS: usize = 4;
+pub const MAX_VOLUME_TIERS: usize = 4;
+pub const MAX_LOTTERY_TICKETS: u64 = 10;
+pub const LOTTERY_REVEAL_PERIOD: i64 = 3 * 24 * 3600;
+pub const LOTTERY_DRAW_DELAY: u64 = 10;
+pub const LOTTERY_BOND_BPS: u64 = 1_000;
+pub const LOTTERY_REARM_PENALTY_BPS: u64 = 2_500;
+pub const TRANCHE_VOTE_GRACE_PERIOD: i64 = 7 * 24 * 3600;
+pub const TRANCHE_VOTE_QUORUM_BPS: u64 = 1_000;
+pub const BASIS_POINTS: u64 = presale_math::BASIS_POINTS;
 
+pub const TOKEN_METADATA_PROGRAM_ID: Pubkey = anchor_lang::solana_program::pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
@@ -5,12 +5,15 @@ pub enum PresaleError {
     #[msg("Invalid owner.")]
     InvalidOwner,
 
+    /// Deprecated: replaced by the sale state lifecycle, kept so later codes don't shift.
     #[msg("The current sale type should be private.")]
     PrivateSale, 
 
+    /// Deprecated: replaced by the sale state lifecycle, kept so later codes don't shift.
     #[msg("The current sale type should be public.")]
     PublicSale, 
 
+    /// Deprecated: replaced by the sale state lifecycle, kept so later codes don't shift.
     #[msg("The contract's status should be live.")]
     NotLive,
 
@@ -30,5 +33,143 @@ pub enum PresaleError {
     NotStaking, 
 
     #[msg("You already claimed token in this period.")]
-    AlreadyClaim
+    AlreadyClaim,
+
+    #[msg("The price moved beyond the given slippage limit.")]
+    SlippageExceeded,
+
+    #[msg("The instruction is not allowed in the current sale state.")]
+    InvalidSaleState,
+
+    #[msg("The sale state can't move to the requested state.")]
+    InvalidStateTransition,
+
+    #[msg("The presale is already initialized.")]
+    AlreadyInitialized,
+
+    #[msg("The release schedule is invalid.")]
+    InvalidReleaseSchedule,
+
+    #[msg("The amount is more than the unlocked sol.")]
+    SolLocked,
+
+    #[msg("The tranche vote is not open.")]
+    VoteNotOpen,
+
+    #[msg("The tranche vote is still running.")]
+    VoteRunning,
+
+    #[msg("There is no tranche left to vote on.")]
+    NoTrancheLeft,
+
+    #[msg("The tranche can't be voted on yet.")]
+    TrancheLocked,
+
+    #[msg("The revenue split is invalid.")]
+    InvalidRecipients,
+
+    #[msg("The raised sol is paid out through distribute.")]
+    RevenueSplitActive,
+
+    #[msg("Invalid platform authority.")]
+    InvalidPlatformAuthority,
+
+    #[msg("The platform fee is invalid.")]
+    InvalidFee,
+
+    #[msg("You can't refer yourself.")]
+    SelfReferral,
+
+    #[msg("Referred users and referrers can't be chained.")]
+    ReferralChain,
+
+    #[msg("The referrer doesn't match the user's referrer.")]
+    InvalidReferrer,
+
+    #[msg("The bonus schedule is invalid.")]
+    InvalidBonusSchedule,
+
+    #[msg("The voucher is invalid.")]
+    InvalidVoucher,
+
+    #[msg("The voucher is expired.")]
+    VoucherExpired,
+
+    #[msg("The purchase exceeds the voucher amount.")]
+    VoucherAmountExceeded,
+
+    #[msg("You don't hold the gate token.")]
+    NotTokenHolder,
+
+    #[msg("The purchase exceeds your private sale allocation.")]
+    AllocationExceeded,
+
+    #[msg("Invalid overflow sale.")]
+    InvalidOverflowSale,
+
+    #[msg("The commitment window is closed.")]
+    CommitmentClosed,
+
+    #[msg("The commitment window is still open.")]
+    CommitmentOpen,
+
+    #[msg("Purchases go through commitments in an overflow sale.")]
+    OverflowSale,
+
+    #[msg("Invalid dutch auction.")]
+    InvalidAuction,
+
+    #[msg("The auction is not open.")]
+    AuctionClosed,
+
+    #[msg("The auction is still running.")]
+    AuctionRunning,
+
+    #[msg("Invalid lottery.")]
+    InvalidLottery,
+
+    #[msg("Purchases go through ticket registration in a lottery sale.")]
+    LotterySale,
+
+    #[msg("The ticket registration is closed.")]
+    RegistrationClosed,
+
+    #[msg("The ticket registration is still open.")]
+    RegistrationOpen,
+
+    #[msg("You already registered tickets.")]
+    AlreadyRegistered,
+
+    #[msg("The seed doesn't match the committed hash.")]
+    InvalidSeed,
+
+    #[msg("The reveal period is over.")]
+    RevealExpired,
+
+    #[msg("You are not whitelisted.")]
+    NotWhitelisted,
+
+    #[msg("The presale mints its tokens and holds no inventory.")]
+    MintOnDemand,
+
+    #[msg("The presale is not the mint authority.")]
+    InvalidMintAuthority,
+
+    #[msg("The vault balance moved by an unexpected amount.")]
+    BalanceMismatch,
+
+    #[msg("Math overflow.")]
+    MathOverflow,
+
+    #[msg("The account is not an account of this program.")]
+    InvalidMigration,
+
+    #[msg("The account already has the current layout.")]
+    AlreadyMigrated,
+
+    #[msg("A migrated sale can't be cancelled.")]
+    MigratedSale,
+
+    #[msg("The draw slot is not reached yet.")]
+    DrawSlotPending
 }
@@ -0,0 +1,121 @@
+use anchor_lang::prelude::*;
+
+use crate::state::*;
+
+#[event]
+pub struct TokenPurchased {
+    pub user: Pubkey,
+    pub state: SaleState, // phase the purchase happened in
+    pub token_amount: u64, // tokens sent to or staked for the user, bonus included
+    pub bonus_amount: u64, // early-bird bonus tokens
+    pub volume_bonus_amount: u64, // volume bonus tokens
+    pub sol_amount: u64, // lamports paid, platform fee included
+    pub fee_amount: u64, // platform fee taken from the payment
+    pub token_price: u64, // token price after the purchase
+}
+
+#[event]
+pub struct FeeCollected {
+    pub user: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct FeeWithdrawn {
+    pub authority: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct PlatformUpdated {
+    pub authority: Pubkey,
+    pub fee_bps: u64,
+}
+
+#[event]
+pub struct ReferralRewarded {
+    pub referrer: Pubkey, // Referrer account
+    pub user: Pubkey, // Referred buyer
+    pub sol_amount: u64,
+    pub token_amount: u64,
+}
+
+#[event]
+pub struct ReferralClaimed {
+    pub referrer: Pubkey, // Referrer account
+    pub owner: Pubkey,
+    pub sol_amount: u64,
+    pub token_amount: u64,
+}
+
+#[event]
+pub struct SolCommitted {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub total_committed: u64, // commitments of all users so far
+}
+
+#[event]
+pub struct OverflowSettled {
+    pub total_committed: u64,
+    pub sol_raised: u64, // committed sol kept by the sale, platform fee included
+    pub fee_amount: u64,
+    pub token_sold: u64,
+}
+
+#[event]
+pub struct OverflowClaimed {
+    pub user: Pubkey,
+    pub token_amount: u64,
+    pub excess_sol: u64, // oversubscribed part of the commitment sent back
+}
+
+#[event]
+pub struct AuctionSettled {
+    pub token_sold: u64,
+    pub clearing_price: u64, // price every bid settles at
+    pub sol_raised: u64, // lamports kept by the sale, platform fee included
+    pub fee_amount: u64,
+}
+
+#[event]
+pub struct AuctionClaimed {
+    pub user: Pubkey,
+    pub token_amount: u64,
+    pub excess_sol: u64, // difference between the bid prices and the clearing price sent back
+}
+
+#[event]
+pub struct TicketsRegistered {
+    pub user: Pubkey,
+    pub first_ticket: u64,
+    pub ticket_count: u64,
+    pub sol_amount: u64,
+}
+
+#[event]
+pub struct LotteryDrawn {
+    pub seed: [u8; 32], // revealed seed, the winners can be recomputed from it and the slot hash
+    pub slot_hash: [u8; 32],
+    pub ticket_count: u64,
+    pub winner_count: u64,
+    pub stride: u64,
+    pub offset: u64,
+}
+
+#[event]
+pub struct LotteryClaimed {
+    pub user: Pubkey,
+    pub token_amount: u64,
+    pub excess_sol: u64, // refund of the losing tickets
+    pub bond_share: u64, // share of the owner's slashed bond
+}
+
+#[event]
+pub struct VaultReconciled {
+    pub token_expected: u64, // token vault balance according to the books
+    pub token_actual: u64,
+    pub sol_expected: u64, // sol vault lamports according to the books
+    pub sol_actual: u64,
+    pub corrected: bool, // the owner synced the books to the actual balances
+}
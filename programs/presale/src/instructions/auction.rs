@@ -0,0 +1,171 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    state::*, constants::*, error::*, events::*,
+    instructions::{send_tokens, SaleManagement, SettleSale},
+    math::{checked, SafeMath},
+};
+use anchor_spl::token_interface::Mint;
+use presale_math::{lamports_for_tokens, Rounding};
+use solana_program::{program::invoke_signed, system_instruction};
+
+pub fn start_dutch_auction(
+    ctx: Context<StartDutchAuction>,
+    token_amount: u64,
+    start_price: u64,
+    floor_price: u64,
+    start_time: i64,
+    end_time: i64
+) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+    accts.presale.require_state(&[SaleState::Draft])?;
+    require!(!accts.presale.auction.is_active(), PresaleError::AlreadyInitialized);
+    require!(accts.presale.overflow_token_amount == 0 && !accts.presale.lottery.is_active(), PresaleError::InvalidAuction);
+    require!(token_amount != 0 && floor_price != 0 && floor_price <= start_price, PresaleError::InvalidAuction);
+    require!(start_time < end_time && end_time > Clock::get()?.unix_timestamp, PresaleError::InvalidAuction);
+    require!(token_amount <= accts.presale.token_amount, PresaleError::InsufficientBalance);
+
+    // the auctioned tokens are set aside from the inventory
+    accts.presale.token_amount = accts.presale.token_amount.safe_sub(token_amount)?;
+    accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_add(token_amount)?;
+    accts.presale.auction = DutchAuction {
+        token_amount,
+        decimals: accts.token_mint.decimals,
+        start_price,
+        floor_price,
+        start_time,
+        end_time,
+        ..Default::default()
+    };
+
+    Ok(())
+}
+
+pub fn settle_auction(ctx: Context<SettleSale>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    accts.presale.require_state(&[SaleState::Public, SaleState::Paused, SaleState::Ended, SaleState::Finalized])?;
+    require!(accts.presale.auction.is_active(), PresaleError::InvalidAuction);
+    require!(!accts.presale.auction.settled, PresaleError::AlreadyInitialized);
+    let current_timestamp = Clock::get()?.unix_timestamp;
+    require!(accts.presale.auction.is_over(current_timestamp), PresaleError::AuctionRunning);
+
+    // a sold out auction clears at its last bid, otherwise at the floor
+    let auction = &mut accts.presale.auction;
+    if auction.token_sold != auction.token_amount {
+        auction.clearing_price = auction.floor_price;
+    }
+    let unsold_amount = auction.token_amount.safe_sub(auction.token_sold)?;
+    let token_sold = auction.token_sold;
+    let sol_raised = checked(lamports_for_tokens(token_sold, auction.clearing_price, auction.decimals, Rounding::Down))?;
+
+    // the platform fee is taken out of the raised sol
+    let fee_amount = accts.platform.fee_on(sol_raised)?;
+    if fee_amount != 0 {
+        let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], ctx.program_id);
+
+        invoke_signed(
+            &system_instruction::transfer(&accts.vault.key(), &accts.fee_vault.key(), fee_amount),
+            &[
+                accts.vault.clone(),
+                accts.fee_vault.clone(),
+                accts.system_program.to_account_info().clone(),
+            ],
+            &[&[VAULT_SEED, &[bump]]],
+        )?;
+        accts.platform.total_fee_collected = accts.platform.total_fee_collected.safe_add(fee_amount)?;
+    }
+
+    let sol_amount = sol_raised.safe_sub(fee_amount)?;
+    accts.presale.auction.sol_raised = sol_amount;
+    accts.presale.auction.settled = true;
+    accts.presale.token_amount = accts.presale.token_amount.safe_add(unsold_amount)?;
+    accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_sub(unsold_amount)?;
+    accts.presale.reserved_sol_amount = accts.presale.reserved_sol_amount.safe_sub(sol_raised)?;
+    accts.presale.sol_amount = accts.presale.sol_amount.safe_add(sol_amount)?;
+    accts.presale.total_sol_raised = accts.presale.total_sol_raised.safe_add(sol_amount)?;
+    accts.presale.total_token_sold = accts.presale.total_token_sold.safe_add(token_sold)?;
+
+    emit!(AuctionSettled {
+        token_sold,
+        clearing_price: accts.presale.auction.clearing_price,
+        sol_raised,
+        fee_amount,
+    });
+
+    Ok(())
+}
+
+pub fn claim_auction<'info>(ctx: Context<'_, '_, '_, 'info, SaleManagement<'info>>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    // a cancelled sale pays the bids back through refund
+    require!(accts.presale.state != SaleState::Cancelled, PresaleError::InvalidSaleState);
+    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+    require!(accts.presale.auction.settled, PresaleError::AuctionRunning);
+    require!(accts.user_info.auction_token_amount != 0, PresaleError::InsufficientBalance);
+
+    let allocation = accts.presale.auction.allocation(
+        accts.user_info.auction_token_amount,
+        accts.user_info.auction_sol_paid
+    )?;
+    accts.user_info.auction_token_amount = 0;
+    accts.user_info.auction_sol_paid = 0;
+    accts.user_info.sol_contributed = accts.user_info.sol_contributed.safe_add(allocation.sol_amount)?;
+    accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_sub(allocation.token_amount)?;
+    accts.presale.reserved_sol_amount = accts.presale.reserved_sol_amount.safe_sub(allocation.excess_sol)?;
+    accts.presale.close_claim()?;
+
+    // send the tokens to the user's token account
+    let received = send_tokens(
+        &mut accts.presale,
+        &accts.token_mint,
+        &accts.token_vault_account,
+        &accts.token_account,
+        &accts.token_program,
+        ctx.remaining_accounts,
+        allocation.token_amount
+    )?;
+    accts.user_info.token_received = accts.user_info.token_received.safe_add(received)?;
+
+    // the difference between the bid prices and the clearing price goes back to the user
+    if allocation.excess_sol != 0 {
+        let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], ctx.program_id);
+
+        invoke_signed(
+            &system_instruction::transfer(&accts.vault.key(), &accts.user.key(), allocation.excess_sol),
+            &[
+                accts.vault.clone(),
+                accts.user.to_account_info().clone(),
+                accts.system_program.to_account_info().clone(),
+            ],
+            &[&[VAULT_SEED, &[bump]]],
+        )?;
+    }
+
+    emit!(AuctionClaimed {
+        user: accts.user.key(),
+        token_amount: allocation.token_amount,
+        excess_sol: allocation.excess_sol,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct StartDutchAuction<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut, 
+        seeds = [PRESALE_SEED],
+        bump,
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+}
@@ -1,61 +1,97 @@
 use anchor_lang::prelude::*;
 
-use crate::{state::*, constants::*, error::*};
+use crate::{state::*, constants::*, error::*, instructions::{send_tokens, transfer_tokens}, math::SafeMath};
 
 use std::mem::size_of;
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{ self, Mint, Token, TokenAccount, Transfer }
+    token_interface::{ self, Burn, Mint, TokenAccount, TokenInterface }
 };
 use solana_program::{program::invoke_signed, system_instruction};
 
-pub fn initialize(
-    ctx: Context<Initialize>,
+pub fn initialize<'info>(
+    ctx: Context<'_, '_, '_, 'info, Initialize<'info>>,
     amount: u64,
-    token_price: u64
+    token_price: u64,
+    unsold_token_mode: UnsoldTokenMode,
+    release_schedule: ReleaseSchedule
 ) -> Result<()> {
     let accts = ctx.accounts;
 
+    // the configuration is immutable once set
+    require!(accts.presale.owner == Pubkey::default(), PresaleError::AlreadyInitialized);
+    release_schedule.validate()?;
+
     // update the presale account with data
     accts.presale.owner = accts.owner.key();
     accts.presale.vault = accts.vault.key();
     accts.presale.token_vault = accts.token_vault_account.key();
     accts.presale.token = accts.token_mint.key();
-    accts.presale.token_amount = amount;
     accts.presale.sol_amount = 0;
     accts.presale.token_price = token_price;
-    accts.presale.status = false;
-    accts.presale.sale_type = false;
-
-    // send presale token to the contract
-    let cpi_accounts = Transfer {
-        from: accts.token_account.to_account_info(),
-        to: accts.token_vault_account.to_account_info(),
-        authority: accts.owner.to_account_info(),
-    };
-    let cpi_context = CpiContext::new(accts.token_program.to_account_info(), cpi_accounts);
-    let _ = token::transfer(cpi_context, amount);
+    accts.presale.state = SaleState::Draft;
+    accts.presale.unsold_token_mode = unsold_token_mode;
+    accts.presale.total_sol_raised = 0;
+    accts.presale.total_token_sold = 0;
+    accts.presale.release_schedule = release_schedule;
+    accts.presale.sol_withdrawn = 0;
+
+    // send presale token to the contract, the inventory is what actually arrives in the vault
+    let delta = transfer_tokens(
+        &accts.token_program,
+        accts.token_account.to_account_info(),
+        &accts.token_mint,
+        accts.token_vault_account.to_account_info(),
+        accts.owner.to_account_info(),
+        ctx.remaining_accounts,
+        &[],
+        amount
+    )?;
+    accts.presale.token_amount = delta.received;
+
+    Ok(())
+}
+
+pub fn enable_mint_on_demand(ctx: Context<EnableMintOnDemand>, max_supply: u64) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.presale.owner == accts.owner.key(), PresaleError::InvalidOwner);
+    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+    accts.presale.require_state(&[SaleState::Draft])?;
+    require!(!accts.presale.mint_on_demand, PresaleError::AlreadyInitialized);
+    // the deposited inventory has to be withdrawn first
+    require!(accts.presale.token_amount == 0, PresaleError::MintOnDemand);
+    require!(
+        accts.token_mint.mint_authority == Some(accts.presale.key()).into(),
+        PresaleError::InvalidMintAuthority
+    );
+
+    accts.presale.mint_on_demand = true;
+    accts.presale.max_supply = max_supply;
+    accts.presale.token_amount = max_supply;
 
     Ok(())
 }
 
-pub fn toggle_status(ctx: Context<ManagePresale>) -> Result<()> {
+pub fn update_sale_state(ctx: Context<ManagePresale>, new_state: SaleState) -> Result<()> {
     let accts = ctx.accounts;
 
     require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
-    // toggle contract's status
-    accts.presale.status = !accts.presale.status;
+    // finalization and cancellation settle the vaults, so they have their own instructions
+    require!(
+        new_state != SaleState::Finalized && new_state != SaleState::Cancelled,
+        PresaleError::InvalidStateTransition
+    );
+    accts.presale.transition_to(new_state)?;
 
     Ok(())
 }
 
-pub fn update_sale_type(ctx: Context<ManagePresale>) -> Result<()> {
+pub fn cancel_sale(ctx: Context<ManagePresale>) -> Result<()> {
     let accts = ctx.accounts;
 
     require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
-    require!(accts.presale.sale_type == false, PresaleError::PrivateSale);
-    // update sale type
-    accts.presale.sale_type = true;
+    accts.presale.cancel()?;
 
     Ok(())
 }
@@ -64,7 +100,7 @@ pub fn update_rate(ctx: Context<ManagePresale>, rate: u64) -> Result<()> {
     let accts = ctx.accounts;
 
     require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
-    require!(accts.presale.sale_type == true, PresaleError::PublicSale);
+    accts.presale.require_state(&[SaleState::Draft, SaleState::Private, SaleState::Public, SaleState::Paused])?;
     accts.presale.rate = rate;
 
     Ok(())
@@ -74,7 +110,7 @@ pub fn update_token_price(ctx: Context<ManagePresale>, new_price: u64) -> Result
     let accts = ctx.accounts;
 
     require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
-    require!(accts.presale.sale_type == false, PresaleError::PrivateSale);
+    accts.presale.require_state(&[SaleState::Draft, SaleState::Private])?;
 
     // update token price and it is avaiable in private sale
     accts.presale.token_price = new_price;
@@ -82,10 +118,99 @@ pub fn update_token_price(ctx: Context<ManagePresale>, new_price: u64) -> Result
     Ok(())
 }
 
+pub fn update_early_bird_bonus(ctx: Context<ManagePresale>, windows: [BonusWindow; MAX_BONUS_WINDOWS]) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+    accts.presale.require_state(&[SaleState::Draft, SaleState::Private, SaleState::Public, SaleState::Paused])?;
+
+    // the windows follow each other
+    let mut last_end = 0;
+    for window in windows.iter().filter(|w| w.bonus_bps != 0) {
+        require!(window.end_offset > last_end, PresaleError::InvalidBonusSchedule);
+        require!(window.bonus_bps <= BASIS_POINTS, PresaleError::InvalidBonusSchedule);
+        last_end = window.end_offset;
+    }
+    accts.presale.early_bird_bonus = windows;
+
+    Ok(())
+}
+
+pub fn update_volume_bonus(ctx: Context<ManagePresale>, tiers: [VolumeTier; MAX_VOLUME_TIERS]) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+    accts.presale.require_state(&[SaleState::Draft, SaleState::Private, SaleState::Public, SaleState::Paused])?;
+
+    // higher tiers need more sol and never pay less
+    let mut last = VolumeTier::default();
+    for tier in tiers.iter().filter(|t| t.bonus_bps != 0) {
+        require!(tier.min_sol_paid > last.min_sol_paid, PresaleError::InvalidBonusSchedule);
+        require!(tier.bonus_bps > last.bonus_bps, PresaleError::InvalidBonusSchedule);
+        require!(tier.bonus_bps <= BASIS_POINTS, PresaleError::InvalidBonusSchedule);
+        last = *tier;
+    }
+    accts.presale.volume_bonus = tiers;
+
+    Ok(())
+}
+
+pub fn update_referral(ctx: Context<ManagePresale>, referral_bps: u64, referral_reward: ReferralReward) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+    accts.presale.require_state(&[SaleState::Draft, SaleState::Private, SaleState::Public, SaleState::Paused])?;
+    require!(referral_bps <= BASIS_POINTS, PresaleError::InvalidFee);
+
+    accts.presale.referral_bps = referral_bps;
+    accts.presale.referral_reward = referral_reward;
+
+    Ok(())
+}
+
+pub fn update_voucher_signer(ctx: Context<ManagePresale>, voucher_signer: Pubkey) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+    accts.presale.require_state(&[SaleState::Draft, SaleState::Private, SaleState::Public, SaleState::Paused])?;
+    accts.presale.voucher_signer = voucher_signer;
+
+    Ok(())
+}
+
+pub fn update_token_gate(
+    ctx: Context<ManageTokenGate>,
+    gate_mode: GateMode,
+    gate_sol_per_token: u64,
+    gate_max_allocation: u64
+) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+    accts.presale.require_state(&[SaleState::Draft, SaleState::Private, SaleState::Public, SaleState::Paused])?;
+
+    accts.presale.gate_mode = gate_mode;
+    accts.presale.gate_mint = accts.gate_mint.key();
+    accts.presale.gate_decimals = accts.gate_mint.decimals;
+    accts.presale.gate_sol_per_token = gate_sol_per_token;
+    accts.presale.gate_max_allocation = gate_max_allocation;
+
+    Ok(())
+}
+
 pub fn update_owner(ctx: Context<ManagePresale>, new_owner: Pubkey) -> Result<()> {
     let accts = ctx.accounts;
 
     require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+    accts.presale.require_state(&[
+        SaleState::Draft,
+        SaleState::Private,
+        SaleState::Public,
+        SaleState::Paused,
+        SaleState::Ended,
+        SaleState::Finalized,
+        SaleState::Cancelled
+    ])?;
 
     // update token price and it is avaiable in private sale
     accts.presale.owner = new_owner;
@@ -97,10 +222,16 @@ pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
     let accts = ctx.accounts;
 
     require!(accts.presale.owner == accts.owner.key(), PresaleError::InvalidOwner);
+    // the vault is frozen for refunds once the sale is cancelled
+    require!(accts.presale.state != SaleState::Cancelled, PresaleError::InvalidSaleState);
+    require!(accts.presale.active_recipients().next().is_none(), PresaleError::RevenueSplitActive);
 
     let lamports = accts.vault.to_account_info().lamports();
     require!(amount <= lamports, PresaleError::InsufficientBalance);
 
+    let current_timestamp = Clock::get()?.unix_timestamp;
+    require!(amount <= accts.presale.withdrawable_sol(current_timestamp)?, PresaleError::SolLocked);
+
     let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], &crate::ID);
 
     invoke_signed(
@@ -112,38 +243,95 @@ pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
         ],
         &[&[VAULT_SEED, &[bump]]],
     )?;
-    accts.presale.sol_amount -= amount;
+    accts.presale.sol_amount = accts.presale.sol_amount.safe_sub(amount)?;
+    accts.presale.sol_withdrawn = accts.presale.sol_withdrawn.safe_add(amount)?;
  
     Ok(())
 }
 
-pub fn deposit_token(ctx: Context<ManageToken>, amount: u64) -> Result<()> {
+pub fn withdraw_donated_sol(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
     let accts = ctx.accounts;
 
     require!(accts.presale.owner == accts.owner.key(), PresaleError::InvalidOwner);
-    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+    // donations were never raised nor refundable, they can be withdrawn in any state
+    accts.presale.require_state(&[
+        SaleState::Draft,
+        SaleState::Private,
+        SaleState::Public,
+        SaleState::Paused,
+        SaleState::Ended,
+        SaleState::Finalized,
+        SaleState::Cancelled
+    ])?;
+    require!(amount <= accts.presale.donated_sol_amount, PresaleError::InsufficientBalance);
 
-    // send presale token to the contract
-    let cpi_accounts = Transfer {
-        from: accts.token_account.to_account_info(),
-        to: accts.token_vault_account.to_account_info(),
-        authority: accts.owner.to_account_info(),
-    };
-    let cpi_context = CpiContext::new(accts.token_program.to_account_info(), cpi_accounts);
-    let _ = token::transfer(cpi_context, amount);
-    accts.presale.token_amount += amount;
+    let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], &crate::ID);
+
+    invoke_signed(
+        &system_instruction::transfer(&accts.vault.key(), &accts.owner.key(), amount),
+        &[
+            accts.vault.to_account_info().clone(),
+            accts.owner.to_account_info().clone(),
+            accts.system_program.to_account_info().clone(),
+        ],
+        &[&[VAULT_SEED, &[bump]]],
+    )?;
+    accts.presale.donated_sol_amount = accts.presale.donated_sol_amount.safe_sub(amount)?;
+
+    Ok(())
+}
+
+pub fn deposit_token<'info>(ctx: Context<'_, '_, '_, 'info, ManageToken<'info>>, amount: u64) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.presale.owner == accts.owner.key(), PresaleError::InvalidOwner);
+    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+    require!(!accts.presale.mint_on_demand, PresaleError::MintOnDemand);
+    accts.presale.require_state(&[SaleState::Draft, SaleState::Private, SaleState::Public, SaleState::Paused])?;
+
+    // send presale token to the contract, the inventory is what actually arrives in the vault
+    let delta = transfer_tokens(
+        &accts.token_program,
+        accts.token_account.to_account_info(),
+        &accts.token_mint,
+        accts.token_vault_account.to_account_info(),
+        accts.owner.to_account_info(),
+        ctx.remaining_accounts,
+        &[],
+        amount
+    )?;
+    accts.presale.token_amount = accts.presale.token_amount.safe_add(delta.received)?;
 
     Ok(())
 }
 
 
-pub fn withdraw_token(ctx: Context<ManageToken>, amount: u64) -> Result<()> {
+pub fn withdraw_token<'info>(ctx: Context<'_, '_, '_, 'info, ManageToken<'info>>, amount: u64) -> Result<()> {
     let accts = ctx.accounts;
 
     require!(accts.presale.owner == accts.owner.key(), PresaleError::InvalidOwner);
     require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
 
-    let balance = accts.presale.token_amount;
+    // finalize already settled the unsold tokens, unless it kept them in the vault.
+    // a mint-on-demand vault only holds donated tokens, they can be withdrawn any time
+    let kept_for_rewards = accts.presale.state == SaleState::Finalized
+        && accts.presale.unsold_token_mode == UnsoldTokenMode::KeepForRewards;
+    if !kept_for_rewards && !accts.presale.mint_on_demand {
+        accts.presale.require_state(&[
+            SaleState::Draft,
+            SaleState::Private,
+            SaleState::Public,
+            SaleState::Paused,
+            SaleState::Ended,
+            SaleState::Cancelled
+        ])?;
+    }
+
+    let balance = if accts.presale.mint_on_demand {
+        accts.presale.donated_token_amount
+    } else {
+        accts.presale.token_amount
+    };
     require!(amount <= balance, PresaleError::InsufficientBalance);
 
     let (_, bump) = Pubkey::find_program_address(&[PRESALE_SEED], ctx.program_id);
@@ -151,14 +339,69 @@ pub fn withdraw_token(ctx: Context<ManageToken>, amount: u64) -> Result<()> {
     let signer = &[&vault_seeds[..]];
 
     // Transfer tokens from bridge to beneficiary
-    let cpi_accounts = Transfer {
-        from: accts.token_vault_account.to_account_info(),
-        to: accts.token_account.to_account_info(),
-        authority: accts.presale.to_account_info(),
-    };
-    let cpi_context = CpiContext::new(accts.token_program.to_account_info(), cpi_accounts);
-    token::transfer(cpi_context.with_signer(signer), amount)?;
-    accts.presale.token_amount -= amount;
+    let delta = transfer_tokens(
+        &accts.token_program,
+        accts.token_vault_account.to_account_info(),
+        &accts.token_mint,
+        accts.token_account.to_account_info(),
+        accts.presale.to_account_info(),
+        ctx.remaining_accounts,
+        signer,
+        amount
+    )?;
+    if accts.presale.mint_on_demand {
+        accts.presale.donated_token_amount = accts.presale.donated_token_amount.safe_sub(delta.sent)?;
+    } else {
+        accts.presale.token_amount = accts.presale.token_amount.safe_sub(delta.sent)?;
+    }
+
+    Ok(())
+}
+
+pub fn finalize<'info>(ctx: Context<'_, '_, '_, 'info, ManageToken<'info>>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.presale.owner == accts.owner.key(), PresaleError::InvalidOwner);
+    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+
+    // close the sale for good, staked tokens stay claimable
+    accts.presale.transition_to(SaleState::Finalized)?;
+    msg!("total sol raised {:?}", accts.presale.total_sol_raised);
+    msg!("total token sold {:?}", accts.presale.total_token_sold);
+
+    // only the unsold tokens leave, the staked_amount stays locked in the vault
+    let unsold_amount = accts.presale.token_amount;
+    if unsold_amount == 0 || accts.presale.unsold_token_mode == UnsoldTokenMode::KeepForRewards {
+        return Ok(());
+    }
+
+    if accts.presale.mint_on_demand {
+        // unsold tokens were never minted, there is nothing to return or burn
+        msg!("unminted supply {:?}", unsold_amount);
+    } else if accts.presale.unsold_token_mode == UnsoldTokenMode::ReturnToOwner {
+        send_tokens(
+            &mut accts.presale,
+            &accts.token_mint,
+            &accts.token_vault_account,
+            &accts.token_account,
+            &accts.token_program,
+            ctx.remaining_accounts,
+            unsold_amount
+        )?;
+    } else {
+        let (_, bump) = Pubkey::find_program_address(&[PRESALE_SEED], ctx.program_id);
+        let vault_seeds = &[PRESALE_SEED, &[bump]];
+        let signer = &[&vault_seeds[..]];
+
+        let cpi_accounts = Burn {
+            mint: accts.token_mint.to_account_info(),
+            from: accts.token_vault_account.to_account_info(),
+            authority: accts.presale.to_account_info(),
+        };
+        let cpi_context = CpiContext::new(accts.token_program.to_account_info(), cpi_accounts);
+        token_interface::burn(cpi_context.with_signer(signer), unsold_amount)?;
+    }
+    accts.presale.token_amount = 0;
 
     Ok(())
 }
@@ -185,10 +428,10 @@ pub struct Initialize<'info> {
     pub vault: AccountInfo<'info>,
 
     #[account(mut)]
-    pub token_mint: Box<Account<'info, Mint>>,
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(mut)]
-    pub token_account: Box<Account<'info, TokenAccount>>, // the token account of owner
+    pub token_account: Box<InterfaceAccount<'info, TokenAccount>>, // the token account of owner
 
     #[account(
         init_if_needed,
@@ -196,11 +439,12 @@ pub struct Initialize<'info> {
         seeds = [TOKEN_VAULT_SEED, token_mint.key().as_ref()],
         bump,
         token::mint = token_mint,
-        token::authority = presale
+        token::authority = presale,
+        token::token_program = token_program
     )]
-    pub token_vault_account: Box<Account<'info, TokenAccount>>,
+    pub token_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
@@ -217,6 +461,36 @@ pub struct ManagePresale<'info> {
     pub presale: Box<Account<'info, Presale>>,
 }
 
+#[derive(Accounts)]
+pub struct EnableMintOnDemand<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut, 
+        seeds = [PRESALE_SEED],
+        bump,
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct ManageTokenGate<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut, 
+        seeds = [PRESALE_SEED],
+        bump,
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+
+    pub gate_mint: Box<InterfaceAccount<'info, Mint>>, // the token, or the collection, private buyers have to hold
+}
+
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
     #[account(mut)]
@@ -253,23 +527,25 @@ pub struct ManageToken<'info> {
     pub presale: Box<Account<'info, Presale>>,
 
     #[account(mut)]
-    pub token_mint: Box<Account<'info, Mint>>,
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
         mut,
         associated_token::mint = token_mint,
-        associated_token::authority = owner
+        associated_token::authority = owner,
+        associated_token::token_program = token_program
     )]
-    pub token_account: Box<Account<'info, TokenAccount>>, // the token account of owner
+    pub token_account: Box<InterfaceAccount<'info, TokenAccount>>, // the token account of owner
 
     #[account(
         mut,
         token::mint = token_mint,
-        token::authority = presale
+        token::authority = presale,
+        token::token_program = token_program
     )]
-    pub token_vault_account: Box<Account<'info, TokenAccount>>,
+    pub token_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>
 }
\ No newline at end of file
@@ -0,0 +1,339 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    state::*, constants::*, error::*, events::*,
+    instructions::{send_tokens, CommitSol, SaleManagement},
+    math::{bps_of, SafeMath},
+};
+use solana_program::{program::{invoke, invoke_signed}, system_instruction, sysvar};
+
+pub fn start_lottery(
+    ctx: Context<StartLottery>,
+    token_per_ticket: u64,
+    sol_per_ticket: u64,
+    winning_tickets: u64,
+    registration_end: i64,
+    seed_hash: [u8; 32]
+) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+    accts.presale.require_state(&[SaleState::Draft])?;
+    require!(!accts.presale.lottery.is_active(), PresaleError::AlreadyInitialized);
+    require!(accts.presale.overflow_token_amount == 0 && !accts.presale.auction.is_active(), PresaleError::InvalidLottery);
+    require!(token_per_ticket != 0 && sol_per_ticket != 0 && winning_tickets != 0, PresaleError::InvalidLottery);
+    require!(registration_end > Clock::get()?.unix_timestamp, PresaleError::InvalidLottery);
+
+    // the winners' tokens are set aside from the inventory
+    let token_amount = winning_tickets.safe_mul(token_per_ticket)?;
+    require!(token_amount <= accts.presale.token_amount, PresaleError::InsufficientBalance);
+    accts.presale.token_amount = accts.presale.token_amount.safe_sub(token_amount)?;
+    accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_add(token_amount)?;
+
+    // the owner bonds a share of the most the lottery can raise, slashed if the seed is held back
+    let bond = bps_of(winning_tickets.safe_mul(sol_per_ticket)?, LOTTERY_BOND_BPS)?;
+    invoke(
+        &system_instruction::transfer(
+            &accts.owner.key(),
+            &accts.vault.key(),
+            bond
+        ),
+        &[
+            accts.owner.to_account_info().clone(),
+            accts.vault.clone(),
+            accts.system_program.to_account_info().clone(),
+        ],
+    )?;
+
+    accts.presale.lottery = Lottery {
+        token_per_ticket,
+        sol_per_ticket,
+        winning_tickets,
+        registration_end,
+        seed_hash,
+        bond,
+        ..Default::default()
+    };
+
+    Ok(())
+}
+
+pub fn register_tickets(ctx: Context<CommitSol>, ticket_count: u64) -> Result<()> {
+    let accts = ctx.accounts;
+
+    accts.presale.require_state(&[SaleState::Public])?;
+    require!(accts.presale.lottery.is_active(), PresaleError::InvalidLottery);
+    require!(Clock::get()?.unix_timestamp < accts.presale.lottery.registration_end, PresaleError::RegistrationClosed);
+    // one range of tickets per user, small enough to be checked in a single claim
+    require!(accts.user_info.lottery_ticket_count == 0, PresaleError::AlreadyRegistered);
+    require!(ticket_count != 0 && ticket_count <= MAX_LOTTERY_TICKETS, PresaleError::InvalidLottery);
+
+    let sol_amount = ticket_count.safe_mul(accts.presale.lottery.sol_per_ticket)?;
+
+    // Send sol to the vault, it is not raised until the winners are drawn
+    invoke(
+        &system_instruction::transfer(
+            &accts.user.key(),
+            &accts.vault.key(),
+            sol_amount
+        ),
+        &[
+            accts.user.to_account_info().clone(),
+            accts.vault.clone(),
+            accts.system_program.to_account_info().clone(),
+        ],
+    )?;
+    accts.user_info.user = accts.user.key();
+    accts.user_info.lottery_first_ticket = accts.presale.lottery.ticket_count;
+    accts.user_info.lottery_ticket_count = ticket_count;
+    accts.presale.lottery.ticket_count = accts.presale.lottery.ticket_count.safe_add(ticket_count)?;
+    accts.presale.reserved_sol_amount = accts.presale.reserved_sol_amount.safe_add(sol_amount)?;
+
+    emit!(TicketsRegistered {
+        user: accts.user.key(),
+        first_ticket: accts.user_info.lottery_first_ticket,
+        ticket_count,
+        sol_amount,
+    });
+
+    Ok(())
+}
+
+pub fn close_lottery_registration(ctx: Context<CloseLotteryRegistration>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.presale.lottery.is_active(), PresaleError::InvalidLottery);
+    require!(accts.presale.lottery.draw_slot == 0, PresaleError::RegistrationClosed);
+    require!(Clock::get()?.unix_timestamp >= accts.presale.lottery.registration_end, PresaleError::RegistrationOpen);
+
+    // the ticket count is final, the draw slot is still in the future
+    accts.presale.lottery.draw_slot = Clock::get()?.slot.safe_add(LOTTERY_DRAW_DELAY)?;
+
+    Ok(())
+}
+
+pub fn reveal_lottery(ctx: Context<RevealLottery>, seed: [u8; 32]) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.presale.lottery.is_active(), PresaleError::InvalidLottery);
+    require!(!accts.presale.lottery.revealed, PresaleError::AlreadyInitialized);
+    let current_timestamp = Clock::get()?.unix_timestamp;
+    require!(current_timestamp >= accts.presale.lottery.registration_end, PresaleError::RegistrationOpen);
+    require!(
+        current_timestamp < accts.presale.lottery.registration_end.safe_add(LOTTERY_REVEAL_PERIOD)?,
+        PresaleError::RevealExpired
+    );
+    // anyone holding the committed seed can reveal it
+    require!(accts.presale.lottery.is_seed(&seed), PresaleError::InvalidSeed);
+
+    let slot_hash = match accts.presale.lottery.draw_slot_hash(&accts.slot_hashes)? {
+        Some(slot_hash) => slot_hash,
+        None => {
+            // the draw slot is gone from the sysvar, waiting for another one costs part of the bond
+            accts.presale.lottery.rearm(Clock::get()?.slot)?;
+            msg!("draw slot moved to {:?}, bond left {:?}", accts.presale.lottery.draw_slot, accts.presale.lottery.bond);
+            return Ok(());
+        },
+    };
+
+    let lottery = &mut accts.presale.lottery;
+    lottery.draw(seed, slot_hash)?;
+    let token_sold = lottery.winner_count.safe_mul(lottery.token_per_ticket)?;
+    let unsold_amount = lottery.winning_tickets.safe_sub(lottery.winner_count)?.safe_mul(lottery.token_per_ticket)?;
+    let sol_raised = lottery.winner_count.safe_mul(lottery.sol_per_ticket)?;
+
+    // the platform fee is taken out of the raised sol
+    let fee_amount = accts.platform.fee_on(sol_raised)?;
+    if fee_amount != 0 {
+        let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], ctx.program_id);
+
+        invoke_signed(
+            &system_instruction::transfer(&accts.vault.key(), &accts.fee_vault.key(), fee_amount),
+            &[
+                accts.vault.clone(),
+                accts.fee_vault.clone(),
+                accts.system_program.to_account_info().clone(),
+            ],
+            &[&[VAULT_SEED, &[bump]]],
+        )?;
+        accts.platform.total_fee_collected = accts.platform.total_fee_collected.safe_add(fee_amount)?;
+    }
+
+    // the winners are drawn, the bond left goes back to the owner
+    let bond = accts.presale.lottery.bond;
+    if bond != 0 {
+        let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], ctx.program_id);
+
+        invoke_signed(
+            &system_instruction::transfer(&accts.vault.key(), &accts.owner.key(), bond),
+            &[
+                accts.vault.clone(),
+                accts.owner.clone(),
+                accts.system_program.to_account_info().clone(),
+            ],
+            &[&[VAULT_SEED, &[bump]]],
+        )?;
+        accts.presale.lottery.bond = 0;
+    }
+
+    let sol_amount = sol_raised.safe_sub(fee_amount)?;
+    accts.presale.lottery.sol_raised = sol_amount;
+    accts.presale.token_amount = accts.presale.token_amount.safe_add(unsold_amount)?;
+    accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_sub(unsold_amount)?;
+    accts.presale.reserved_sol_amount = accts.presale.reserved_sol_amount.safe_sub(sol_raised)?;
+    accts.presale.sol_amount = accts.presale.sol_amount.safe_add(sol_amount)?;
+    accts.presale.total_sol_raised = accts.presale.total_sol_raised.safe_add(sol_amount)?;
+    accts.presale.total_token_sold = accts.presale.total_token_sold.safe_add(token_sold)?;
+
+    emit!(LotteryDrawn {
+        seed,
+        slot_hash,
+        ticket_count: accts.presale.lottery.ticket_count,
+        winner_count: accts.presale.lottery.winner_count,
+        stride: accts.presale.lottery.stride,
+        offset: accts.presale.lottery.offset,
+    });
+
+    Ok(())
+}
+
+pub fn claim_lottery<'info>(ctx: Context<'_, '_, '_, 'info, SaleManagement<'info>>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    // a cancelled sale pays the tickets back through refund
+    require!(accts.presale.state != SaleState::Cancelled, PresaleError::InvalidSaleState);
+    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+    require!(accts.user_info.lottery_ticket_count != 0, PresaleError::InsufficientBalance);
+
+    let lottery = accts.presale.lottery;
+    let allocation = if lottery.revealed {
+        lottery.allocation(accts.user_info.lottery_first_ticket, accts.user_info.lottery_ticket_count)?
+    } else {
+        // the seed was never revealed, the lottery ends and every ticket is refunded
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        require!(current_timestamp >= lottery.registration_end.safe_add(LOTTERY_REVEAL_PERIOD)?, PresaleError::RegistrationOpen);
+        accts.presale.end_unrevealed_lottery()?;
+        SettledAllocation {
+            excess_sol: accts.user_info.lottery_ticket_count.safe_mul(lottery.sol_per_ticket)?,
+            ..Default::default()
+        }
+    };
+    // the tickets share what was slashed from the owner's bond
+    let bond_share = accts.presale.lottery.pay_bond_share(accts.user_info.lottery_ticket_count)?;
+    accts.user_info.lottery_ticket_count = 0;
+    accts.user_info.sol_contributed = accts.user_info.sol_contributed.safe_add(allocation.sol_amount)?;
+    accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_sub(allocation.token_amount)?;
+    accts.presale.reserved_sol_amount = accts.presale.reserved_sol_amount.safe_sub(allocation.excess_sol)?;
+
+    if allocation.token_amount != 0 {
+        // send the tokens to the user's token account
+        let received = send_tokens(
+            &mut accts.presale,
+            &accts.token_mint,
+            &accts.token_vault_account,
+            &accts.token_account,
+            &accts.token_program,
+            ctx.remaining_accounts,
+            allocation.token_amount
+        )?;
+        accts.user_info.token_received = accts.user_info.token_received.safe_add(received)?;
+    }
+
+    // losing tickets are refunded
+    let sol_amount = allocation.excess_sol.safe_add(bond_share)?;
+    if sol_amount != 0 {
+        let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], ctx.program_id);
+
+        invoke_signed(
+            &system_instruction::transfer(&accts.vault.key(), &accts.user.key(), sol_amount),
+            &[
+                accts.vault.clone(),
+                accts.user.to_account_info().clone(),
+                accts.system_program.to_account_info().clone(),
+            ],
+            &[&[VAULT_SEED, &[bump]]],
+        )?;
+    }
+
+    emit!(LotteryClaimed {
+        user: accts.user.key(),
+        token_amount: allocation.token_amount,
+        excess_sol: allocation.excess_sol,
+        bond_share,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct StartLottery<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump
+    )]
+    pub vault: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseLotteryRegistration<'info> {
+    #[account(
+        mut,
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+}
+
+#[derive(Accounts)]
+pub struct RevealLottery<'info> {
+    #[account(
+        mut,
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump
+    )]
+    pub vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [PLATFORM_SEED],
+        bump
+    )]
+    pub platform: Box<Account<'info, Platform>>,
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED],
+        bump
+    )]
+    pub fee_vault: AccountInfo<'info>,
+
+    /// CHECK: the presale owner, receives the bond back
+    #[account(mut, address = presale.owner)]
+    pub owner: AccountInfo<'info>,
+
+    /// CHECK: the slot hashes sysvar
+    #[account(address = sysvar::slot_hashes::ID)]
+    pub slot_hashes: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
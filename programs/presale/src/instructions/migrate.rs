@@ -0,0 +1,168 @@
+use anchor_lang::{prelude::*, Discriminator};
+
+use crate::{state::*, constants::*, error::*, instructions::staking_payout, math::SafeMath};
+use solana_program::{program::invoke, system_instruction};
+use std::mem::size_of;
+
+// `Presale` as laid out before the sale lifecycle replaced the status flags
+#[derive(AnchorDeserialize)]
+struct LegacyPresale {
+    owner: Pubkey,
+    vault: Pubkey,
+    token_vault: Pubkey,
+    token: Pubkey,
+    token_amount: u64,
+    sol_amount: u64,
+    token_price: u64,
+    status: bool,
+    sale_type: bool,
+    rate: u64,
+    staked_amount: u64,
+    rate_3m: u64,
+    rate_6m: u64,
+    rate_9m: u64,
+    rate_12m: u64,
+}
+
+pub fn migrate_presale(ctx: Context<MigratePresale>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    let presale = accts.presale.to_account_info();
+    let space = 8 + size_of::<Presale>();
+    require!(presale.data_len() < space, PresaleError::AlreadyMigrated);
+    let legacy = read_legacy_account::<Presale, LegacyPresale>(&presale)?;
+    require!(legacy.owner == accts.owner.key(), PresaleError::InvalidOwner);
+
+    // a live sale keeps its phase, a halted one can resume into either
+    let state = match (legacy.status, legacy.sale_type) {
+        (true, false) => SaleState::Private,
+        (true, true) => SaleState::Public,
+        (false, _) if legacy.sol_amount == 0 && legacy.token_amount == 0 => SaleState::Draft,
+        (false, _) => SaleState::Paused,
+    };
+
+    // contributions weren't tracked before, so older purchases can't be refunded pro-rata
+    let migrated = Presale {
+        owner: legacy.owner,
+        vault: legacy.vault,
+        token_vault: legacy.token_vault,
+        token: legacy.token,
+        token_amount: legacy.token_amount,
+        sol_amount: legacy.sol_amount,
+        token_price: legacy.token_price,
+        state,
+        rate: legacy.rate,
+        staked_amount: legacy.staked_amount,
+        rate_3m: legacy.rate_3m,
+        rate_6m: legacy.rate_6m,
+        rate_9m: legacy.rate_9m,
+        rate_12m: legacy.rate_12m,
+        migrated: true,
+        ..Default::default()
+    };
+
+    grow_account(&presale, &accts.owner, &accts.system_program, space)?;
+    migrated.try_serialize(&mut &mut presale.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+pub fn migrate_user_info(ctx: Context<MigrateUserInfo>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    let user_info = accts.user_info.to_account_info();
+    let space = 8 + size_of::<UserInfo>();
+    require!(user_info.data_len() < space, PresaleError::AlreadyMigrated);
+    require!(user_info.owner == &crate::ID, PresaleError::InvalidMigration);
+    require!(user_info.try_borrow_data()?.starts_with(&UserInfo::DISCRIMINATOR), PresaleError::InvalidMigration);
+
+    // older accounts are a prefix of the current layout, the appended fields start zeroed
+    grow_account(&user_info, &accts.user, &accts.system_program, space)?;
+    let migrated = UserInfo::try_deserialize(&mut &user_info.try_borrow_data()?[..])?;
+
+    // older stakes left their yield in the inventory, lock it like the current ones
+    let stakes = [
+        (3, migrated.stake_amount_3m),
+        (6, migrated.stake_amount_6m),
+        (9, migrated.stake_amount_9m),
+        (12, migrated.stake_amount_12m),
+    ];
+    for (staked_period, amount) in stakes.into_iter().filter(|(_, amount)| *amount != 0) {
+        let locked_amount = staking_payout(staked_period, amount)?;
+        accts.presale.token_amount = accts.presale.token_amount.safe_sub(locked_amount.safe_sub(amount)?)?;
+        accts.presale.staked_amount = accts.presale.staked_amount.safe_add(locked_amount)?;
+    }
+
+    Ok(())
+}
+
+// reads an account of this program written with an older layout of `T`
+fn read_legacy_account<T: Discriminator, L: AnchorDeserialize>(info: &AccountInfo) -> Result<L> {
+    require!(info.owner == &crate::ID, PresaleError::InvalidMigration);
+    let data = info.try_borrow_data()?;
+    require!(data.starts_with(&T::DISCRIMINATOR), PresaleError::InvalidMigration);
+    Ok(L::deserialize(&mut &data[8..])?)
+}
+
+// reallocs the account to `space` bytes, the payer tops up the rent
+fn grow_account<'info>(
+    info: &AccountInfo<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    space: usize
+) -> Result<()> {
+    let rent = Rent::get()?.minimum_balance(space).saturating_sub(info.lamports());
+    if rent != 0 {
+        invoke(
+            &system_instruction::transfer(&payer.key(), &info.key(), rent),
+            &[
+                payer.to_account_info().clone(),
+                info.clone(),
+                system_program.to_account_info().clone(),
+            ],
+        )?;
+    }
+    info.realloc(space, true)?;
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct MigratePresale<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// CHECK: the presale account of an older layout, checked in `migrate_presale`
+    #[account(
+        mut,
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateUserInfo<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: a user info account of an older layout, checked in `migrate_user_info`
+    #[account(
+        mut,
+        seeds = [USER_INFO_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub user_info: AccountInfo<'info>,
+
+    // migrated with `migrate_presale` first
+    #[account(
+        mut,
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+
+    pub system_program: Program<'info, System>,
+}
@@ -1,5 +1,27 @@
+pub mod auction;
 pub mod initialize;
+pub mod lottery;
+pub mod migrate;
+pub mod overflow;
+pub mod payout;
+pub mod platform;
+pub mod reconcile;
+pub mod referral;
 pub mod sale;
+pub mod treasury;
+pub mod vote;
+pub mod whitelist;
 
+pub use auction::*;
 pub use initialize::*;
+pub use lottery::*;
+pub use migrate::*;
+pub use overflow::*;
+pub use payout::*;
+pub use platform::*;
+pub use reconcile::*;
+pub use referral::*;
 pub use sale::*;
+pub use treasury::*;
+pub use vote::*;
+pub use whitelist::*;
@@ -0,0 +1,242 @@
+use anchor_lang::prelude::*;
+
+use crate::{
+    state::*, constants::*, error::*, events::*,
+    instructions::{send_tokens, ManagePresale, SaleManagement},
+    math::{mul_div, SafeMath},
+};
+use solana_program::{program::{invoke, invoke_signed}, system_instruction};
+use std::mem::size_of;
+
+pub fn start_overflow_sale(
+    ctx: Context<ManagePresale>,
+    token_amount: u64,
+    sol_target: u64,
+    commit_end: i64
+) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+    accts.presale.require_state(&[SaleState::Draft])?;
+    require!(accts.presale.overflow_token_amount == 0, PresaleError::AlreadyInitialized);
+    require!(!accts.presale.auction.is_active() && !accts.presale.lottery.is_active(), PresaleError::InvalidOverflowSale);
+    require!(token_amount != 0 && sol_target != 0, PresaleError::InvalidOverflowSale);
+    require!(commit_end > Clock::get()?.unix_timestamp, PresaleError::InvalidOverflowSale);
+    require!(token_amount <= accts.presale.token_amount, PresaleError::InsufficientBalance);
+
+    // the allocation is set aside from the inventory for the committers
+    accts.presale.token_amount = accts.presale.token_amount.safe_sub(token_amount)?;
+    accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_add(token_amount)?;
+    accts.presale.overflow_token_amount = token_amount;
+    accts.presale.overflow_sol_target = sol_target;
+    accts.presale.overflow_end = commit_end;
+
+    Ok(())
+}
+
+pub fn commit_sol(ctx: Context<CommitSol>, amount: u64) -> Result<()> {
+    let accts = ctx.accounts;
+
+    accts.presale.require_state(&[SaleState::Public])?;
+    require!(accts.presale.overflow_token_amount != 0, PresaleError::InvalidOverflowSale);
+    require!(Clock::get()?.unix_timestamp < accts.presale.overflow_end, PresaleError::CommitmentClosed);
+    require!(amount != 0, PresaleError::InsufficientBalance);
+
+    // Send sol to the vault, it is not raised until the sale is settled
+    invoke(
+        &system_instruction::transfer(
+            &accts.user.key(),
+            &accts.vault.key(),
+            amount
+        ),
+        &[
+            accts.user.to_account_info().clone(),
+            accts.vault.clone(),
+            accts.system_program.to_account_info().clone(),
+        ],
+    )?;
+    if accts.user_info.committed == 0 {
+        accts.presale.open_claim()?;
+    }
+    accts.user_info.user = accts.user.key();
+    accts.user_info.committed = accts.user_info.committed.safe_add(amount)?;
+    accts.presale.total_committed = accts.presale.total_committed.safe_add(amount)?;
+    accts.presale.reserved_sol_amount = accts.presale.reserved_sol_amount.safe_add(amount)?;
+
+    emit!(SolCommitted {
+        user: accts.user.key(),
+        amount,
+        total_committed: accts.presale.total_committed,
+    });
+
+    Ok(())
+}
+
+pub fn settle_overflow(ctx: Context<SettleSale>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    accts.presale.require_state(&[SaleState::Public, SaleState::Paused, SaleState::Ended, SaleState::Finalized])?;
+    require!(accts.presale.overflow_token_amount != 0, PresaleError::InvalidOverflowSale);
+    require!(!accts.presale.overflow_settled, PresaleError::AlreadyInitialized);
+    require!(Clock::get()?.unix_timestamp >= accts.presale.overflow_end, PresaleError::CommitmentOpen);
+
+    // an undersubscribed sale sells at the target price and returns the rest of the allocation
+    let total_committed = accts.presale.total_committed;
+    let sol_target = accts.presale.overflow_sol_target;
+    let sol_raised = total_committed.min(sol_target);
+    let token_sold = mul_div(accts.presale.overflow_token_amount, sol_raised, sol_target)?;
+    let unsold_amount = accts.presale.overflow_token_amount.safe_sub(token_sold)?;
+    accts.presale.token_amount = accts.presale.token_amount.safe_add(unsold_amount)?;
+    accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_sub(unsold_amount)?;
+    accts.presale.reserved_sol_amount = accts.presale.reserved_sol_amount.safe_sub(sol_raised)?;
+    accts.presale.overflow_token_amount = token_sold;
+
+    // the platform fee is taken out of the raised sol
+    let fee_amount = accts.platform.fee_on(sol_raised)?;
+    if fee_amount != 0 {
+        let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], ctx.program_id);
+
+        invoke_signed(
+            &system_instruction::transfer(&accts.vault.key(), &accts.fee_vault.key(), fee_amount),
+            &[
+                accts.vault.clone(),
+                accts.fee_vault.clone(),
+                accts.system_program.to_account_info().clone(),
+            ],
+            &[&[VAULT_SEED, &[bump]]],
+        )?;
+        accts.platform.total_fee_collected = accts.platform.total_fee_collected.safe_add(fee_amount)?;
+    }
+
+    let sol_amount = sol_raised.safe_sub(fee_amount)?;
+    accts.presale.overflow_sol_raised = sol_amount;
+    accts.presale.overflow_settled = true;
+    accts.presale.sol_amount = accts.presale.sol_amount.safe_add(sol_amount)?;
+    accts.presale.total_sol_raised = accts.presale.total_sol_raised.safe_add(sol_amount)?;
+    accts.presale.total_token_sold = accts.presale.total_token_sold.safe_add(token_sold)?;
+
+    emit!(OverflowSettled {
+        total_committed,
+        sol_raised,
+        fee_amount,
+        token_sold,
+    });
+
+    Ok(())
+}
+
+pub fn claim_overflow<'info>(ctx: Context<'_, '_, '_, 'info, SaleManagement<'info>>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    // a cancelled sale pays the commitments back through refund
+    require!(accts.presale.state != SaleState::Cancelled, PresaleError::InvalidSaleState);
+    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+    require!(accts.presale.overflow_settled, PresaleError::CommitmentOpen);
+    require!(accts.user_info.committed != 0, PresaleError::InsufficientBalance);
+
+    let allocation = accts.presale.overflow_allocation(accts.user_info.committed)?;
+    accts.user_info.committed = 0;
+    accts.user_info.sol_contributed = accts.user_info.sol_contributed.safe_add(allocation.sol_amount)?;
+    accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_sub(allocation.token_amount)?;
+    accts.presale.reserved_sol_amount = accts.presale.reserved_sol_amount.safe_sub(allocation.excess_sol)?;
+    accts.presale.close_claim()?;
+
+    // send the tokens to the user's token account
+    let received = send_tokens(
+        &mut accts.presale,
+        &accts.token_mint,
+        &accts.token_vault_account,
+        &accts.token_account,
+        &accts.token_program,
+        ctx.remaining_accounts,
+        allocation.token_amount
+    )?;
+    accts.user_info.token_received = accts.user_info.token_received.safe_add(received)?;
+
+    // the oversubscribed part of the commitment goes back to the user
+    if allocation.excess_sol != 0 {
+        let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], ctx.program_id);
+
+        invoke_signed(
+            &system_instruction::transfer(&accts.vault.key(), &accts.user.key(), allocation.excess_sol),
+            &[
+                accts.vault.clone(),
+                accts.user.to_account_info().clone(),
+                accts.system_program.to_account_info().clone(),
+            ],
+            &[&[VAULT_SEED, &[bump]]],
+        )?;
+    }
+
+    emit!(OverflowClaimed {
+        user: accts.user.key(),
+        token_amount: allocation.token_amount,
+        excess_sol: allocation.excess_sol,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CommitSol<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [USER_INFO_SEED, user.key().as_ref()],
+        bump,
+        space = 8 + size_of::<UserInfo>()
+    )]
+    pub user_info: Account<'info, UserInfo>,
+
+    #[account(
+        mut, 
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump
+    )]
+    pub vault: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleSale<'info> {
+    #[account(
+        mut, 
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump
+    )]
+    pub vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [PLATFORM_SEED],
+        bump
+    )]
+    pub platform: Box<Account<'info, Platform>>,
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED],
+        bump
+    )]
+    pub fee_vault: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
@@ -0,0 +1,300 @@
+use anchor_lang::prelude::*;
+
+use crate::{state::*, constants::*, error::*, math::SafeMath};
+use anchor_spl::{
+    token_2022::spl_token_2022,
+    token_interface::{ self, Burn, Mint, MintTo, TokenAccount, TokenInterface }
+};
+use solana_program::{instruction::AccountMeta, program::invoke_signed};
+
+/// Balance changes of the two token accounts of a transfer.
+#[derive(Clone, Copy, Debug)]
+pub struct BalanceDelta {
+    pub sent: u64, // amount that left the source account
+    pub received: u64, // amount that arrived in the destination account, after any transfer fee
+}
+
+/// Current balance of a token account, read from its data.
+pub fn token_balance(token_account: &AccountInfo) -> Result<u64> {
+    let account = TokenAccount::try_deserialize(&mut &token_account.try_borrow_data()?[..])?;
+    Ok(account.amount)
+}
+
+/// Moves `amount` tokens with `transfer_checked`. `extra_accounts` are the
+/// accounts required by the mint's transfer hook, forwarded as they are.
+/// The books follow the balance changes of both accounts rather than `amount`,
+/// so fees or hooks taking a cut can't desynchronize them.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_tokens<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    from: AccountInfo<'info>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    to: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    extra_accounts: &[AccountInfo<'info>],
+    signer_seeds: &[&[&[u8]]],
+    amount: u64
+) -> Result<BalanceDelta> {
+    let from_before = token_balance(&from)?;
+    let to_before = token_balance(&to)?;
+
+    let mut ix = spl_token_2022::instruction::transfer_checked(
+        token_program.key,
+        from.key,
+        &token_mint.key(),
+        to.key,
+        authority.key,
+        &[],
+        amount,
+        token_mint.decimals,
+    )?;
+    let mut account_infos = vec![from.clone(), token_mint.to_account_info(), to.clone(), authority];
+    for account in extra_accounts {
+        ix.accounts.push(if account.is_writable {
+            AccountMeta::new(account.key(), account.is_signer)
+        } else {
+            AccountMeta::new_readonly(account.key(), account.is_signer)
+        });
+        account_infos.push(account.clone());
+    }
+    invoke_signed(&ix, &account_infos, signer_seeds)?;
+
+    Ok(BalanceDelta {
+        sent: from_before.checked_sub(token_balance(&from)?).ok_or(PresaleError::BalanceMismatch)?,
+        received: token_balance(&to)?.checked_sub(to_before).ok_or(PresaleError::BalanceMismatch)?,
+    })
+}
+
+/// Pays `amount` presale tokens to `to`: minted in mint-on-demand mode,
+/// transferred out of the token vault otherwise. Returns the amount `to` receives.
+pub fn send_tokens<'info>(
+    presale: &mut Account<'info, Presale>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    token_vault_account: &InterfaceAccount<'info, TokenAccount>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    token_program: &Interface<'info, TokenInterface>,
+    extra_accounts: &[AccountInfo<'info>],
+    amount: u64
+) -> Result<u64> {
+    let (_, bump) = Pubkey::find_program_address(&[PRESALE_SEED], &crate::ID);
+    let vault_seeds = &[PRESALE_SEED, &[bump]];
+    let signer = &[&vault_seeds[..]];
+
+    if presale.mint_on_demand {
+        require!(presale.minted_amount.safe_add(amount)? <= presale.max_supply, PresaleError::InsufficientBalance);
+
+        let cpi_accounts = MintTo {
+            mint: token_mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: presale.to_account_info(),
+        };
+        let balance_before = token_balance(&to.to_account_info())?;
+        let cpi_context = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+        token_interface::mint_to(cpi_context.with_signer(signer), amount)?;
+        presale.minted_amount = presale.minted_amount.safe_add(amount)?;
+
+        let received = token_balance(&to.to_account_info())?.checked_sub(balance_before);
+        Ok(received.ok_or(PresaleError::BalanceMismatch)?)
+    } else {
+        let delta = transfer_tokens(
+            token_program,
+            token_vault_account.to_account_info(),
+            token_mint,
+            to.to_account_info(),
+            presale.to_account_info(),
+            extra_accounts,
+            signer,
+            amount
+        )?;
+        // the books reserved exactly `amount` for this payout
+        require!(delta.sent == amount, PresaleError::BalanceMismatch);
+
+        Ok(delta.received)
+    }
+}
+
+/// Takes `amount` presale tokens back from `from`, signed by `authority`:
+/// burned in mint-on-demand mode, transferred back to the token vault otherwise.
+/// Returns the amount that goes back to the inventory.
+#[allow(clippy::too_many_arguments)]
+pub fn reclaim_tokens<'info>(
+    presale: &mut Account<'info, Presale>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    token_vault_account: &InterfaceAccount<'info, TokenAccount>,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    authority: &Signer<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    extra_accounts: &[AccountInfo<'info>],
+    amount: u64
+) -> Result<u64> {
+    if presale.mint_on_demand {
+        let cpi_accounts = Burn {
+            mint: token_mint.to_account_info(),
+            from: from.to_account_info(),
+            authority: authority.to_account_info(),
+        };
+        let cpi_context = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+        token_interface::burn(cpi_context, amount)?;
+        presale.minted_amount = presale.minted_amount.safe_sub(amount)?;
+
+        Ok(amount)
+    } else {
+        let delta = transfer_tokens(
+            token_program,
+            from.to_account_info(),
+            token_mint,
+            token_vault_account.to_account_info(),
+            authority.to_account_info(),
+            extra_accounts,
+            &[],
+            amount
+        )?;
+
+        Ok(delta.received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{invoked_accounts, mint_data, set_transfer_fee_bps, stub_syscalls, token_account_data};
+    use anchor_spl::token_2022::ID as TOKEN_2022_ID;
+
+    // accounts of a payout, owned by the test: the presale, the mint, the token
+    // vault, the buyer's token account, the token program, then any hook accounts
+    struct Accounts {
+        keys: Vec<Pubkey>,
+        owners: Vec<Pubkey>,
+        lamports: Vec<u64>,
+        data: Vec<Vec<u8>>,
+    }
+
+    impl Accounts {
+        fn new(presale: &Presale, vault_balance: u64) -> Self {
+            let presale_key = Pubkey::find_program_address(&[PRESALE_SEED], &crate::ID).0;
+            let mint = Pubkey::new_unique();
+            let mut presale_data = Vec::new();
+            presale.try_serialize(&mut presale_data).unwrap();
+            Accounts {
+                keys: vec![presale_key, mint, Pubkey::new_unique(), Pubkey::new_unique(), TOKEN_2022_ID],
+                owners: vec![crate::ID, TOKEN_2022_ID, TOKEN_2022_ID, TOKEN_2022_ID, TOKEN_2022_ID],
+                lamports: vec![0; 5],
+                data: vec![
+                    presale_data,
+                    mint_data(presale_key, 6),
+                    token_account_data(mint, presale_key, vault_balance),
+                    token_account_data(mint, Pubkey::new_unique(), 0),
+                    vec![],
+                ],
+            }
+        }
+
+        fn add_extra_account(&mut self, key: Pubkey, owner: Pubkey) {
+            self.keys.push(key);
+            self.owners.push(owner);
+            self.lamports.push(0);
+            self.data.push(vec![]);
+        }
+
+        fn infos(&mut self) -> Vec<AccountInfo<'_>> {
+            self.keys
+                .iter()
+                .zip(self.owners.iter())
+                .zip(self.lamports.iter_mut())
+                .zip(self.data.iter_mut())
+                .map(|(((key, owner), lamports), data)| {
+                    let executable = key == owner || key == &TOKEN_2022_ID;
+                    AccountInfo::new(key, false, true, lamports, data, owner, executable, 0)
+                })
+                .collect()
+        }
+    }
+
+    // pays `amount` and returns what arrived, with the presale and the vault balance afterwards
+    fn pay(accounts: &mut Accounts, amount: u64) -> Result<(u64, Presale, u64)> {
+        let infos = accounts.infos();
+        let mut presale = Account::<Presale>::try_from(&infos[0])?;
+        let token_mint = InterfaceAccount::<Mint>::try_from(&infos[1])?;
+        let token_vault_account = InterfaceAccount::<TokenAccount>::try_from(&infos[2])?;
+        let to = InterfaceAccount::<TokenAccount>::try_from(&infos[3])?;
+        let token_program = Interface::<TokenInterface>::try_from(&infos[4])?;
+
+        let received = send_tokens(&mut presale, &token_mint, &token_vault_account, &to, &token_program, &infos[5..], amount)?;
+        Ok((received, presale.into_inner(), token_balance(&infos[2])?))
+    }
+
+    #[test]
+    fn mint_on_demand_mints_up_to_the_max_supply() {
+        stub_syscalls();
+        let presale = Presale { mint_on_demand: true, max_supply: 1_000, minted_amount: 600, ..Default::default() };
+        let mut accounts = Accounts::new(&presale, 0);
+
+        let (received, presale, vault_balance) = pay(&mut accounts, 300).unwrap();
+        assert_eq!((received, presale.minted_amount, vault_balance), (300, 900, 0));
+        assert_eq!(token_balance(&accounts.infos()[3]).unwrap(), 300);
+
+        let mut accounts = Accounts::new(&presale, 0);
+        assert_eq!(pay(&mut accounts, 101).err().unwrap(), PresaleError::InsufficientBalance.into());
+        pay(&mut accounts, 100).unwrap();
+    }
+
+    #[test]
+    fn inventory_payouts_come_out_of_the_token_vault() {
+        stub_syscalls();
+        let mut accounts = Accounts::new(&Presale::default(), 500);
+        let (received, presale, vault_balance) = pay(&mut accounts, 200).unwrap();
+        assert_eq!((received, presale.minted_amount, vault_balance), (200, 0, 300));
+    }
+
+    #[test]
+    fn transfer_fee_mints_pay_out_what_arrives() {
+        stub_syscalls();
+        set_transfer_fee_bps(100);
+        let mut accounts = Accounts::new(&Presale::default(), 10_000);
+        // the vault is debited the whole amount, the buyer receives it net of the fee
+        let (received, _, vault_balance) = pay(&mut accounts, 5_000).unwrap();
+        assert_eq!((received, vault_balance), (4_950, 5_000));
+        set_transfer_fee_bps(0);
+    }
+
+    #[test]
+    fn transfer_hook_accounts_are_forwarded() {
+        stub_syscalls();
+        let hook_program = Pubkey::new_unique();
+        let validation = Pubkey::new_unique();
+        let mut accounts = Accounts::new(&Presale::default(), 100);
+        accounts.add_extra_account(validation, hook_program);
+        accounts.add_extra_account(hook_program, hook_program);
+        let keys = accounts.keys.clone();
+
+        pay(&mut accounts, 100).unwrap();
+        // source, mint, destination, authority, then the hook accounts in order
+        assert_eq!(invoked_accounts(), vec![keys[2], keys[1], keys[3], keys[0], validation, hook_program]);
+    }
+
+    // deposits `amount` from the buyer's token account into the token vault
+    fn deposit(accounts: &mut Accounts, amount: u64) -> Result<BalanceDelta> {
+        let infos = accounts.infos();
+        let token_mint = InterfaceAccount::<Mint>::try_from(&infos[1])?;
+        let token_program = Interface::<TokenInterface>::try_from(&infos[4])?;
+        transfer_tokens(&token_program, infos[3].clone(), &token_mint, infos[2].clone(), infos[0].clone(), &[], &[], amount)
+    }
+
+    #[test]
+    fn deposits_are_accounted_by_the_balance_changes() {
+        stub_syscalls();
+        let mut accounts = Accounts::new(&Presale::default(), 0);
+        let mint = accounts.keys[1];
+        accounts.data[3] = token_account_data(mint, Pubkey::new_unique(), 1_000);
+
+        set_transfer_fee_bps(250);
+        let delta = deposit(&mut accounts, 800).unwrap();
+        set_transfer_fee_bps(0);
+        assert_eq!((delta.sent, delta.received), (800, 780));
+        assert_eq!(token_balance(&accounts.infos()[2]).unwrap(), 780);
+
+        // a failed transfer fails the instruction instead of being booked
+        assert!(deposit(&mut accounts, 201).is_err());
+        assert_eq!(token_balance(&accounts.infos()[3]).unwrap(), 200);
+    }
+}
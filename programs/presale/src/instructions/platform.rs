@@ -0,0 +1,149 @@
+use anchor_lang::prelude::*;
+
+use crate::{state::*, constants::*, error::*, events::*};
+use solana_program::{program::{invoke, invoke_signed}, system_instruction};
+use std::mem::size_of;
+
+pub fn initialize_platform(ctx: Context<InitializePlatform>, fee_bps: u64) -> Result<()> {
+    let accts = ctx.accounts;
+
+    accts.platform.authority = accts.authority.key();
+    accts.platform.fee_vault = accts.fee_vault.key();
+    accts.platform.set_fee(fee_bps)?;
+    accts.platform.total_fee_collected = 0;
+
+    // a rent-exempt fee vault accepts fees of any size
+    let rent = Rent::get()?.minimum_balance(0).saturating_sub(accts.fee_vault.lamports());
+    if rent != 0 {
+        invoke(
+            &system_instruction::transfer(&accts.authority.key(), &accts.fee_vault.key(), rent),
+            &[
+                accts.authority.to_account_info().clone(),
+                accts.fee_vault.clone(),
+                accts.system_program.to_account_info().clone(),
+            ],
+        )?;
+    }
+
+    emit!(PlatformUpdated {
+        authority: accts.platform.authority,
+        fee_bps,
+    });
+
+    Ok(())
+}
+
+pub fn update_platform(ctx: Context<ManagePlatform>, new_authority: Pubkey, fee_bps: u64) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.authority.key() == accts.platform.authority, PresaleError::InvalidPlatformAuthority);
+
+    accts.platform.authority = new_authority;
+    accts.platform.set_fee(fee_bps)?;
+
+    emit!(PlatformUpdated {
+        authority: new_authority,
+        fee_bps,
+    });
+
+    Ok(())
+}
+
+pub fn withdraw_fee(ctx: Context<WithdrawFee>, amount: u64) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.authority.key() == accts.platform.authority, PresaleError::InvalidPlatformAuthority);
+
+    // the rent-exempt reserve stays in the vault
+    let lamports = accts.fee_vault.lamports().saturating_sub(Rent::get()?.minimum_balance(0));
+    require!(amount <= lamports, PresaleError::InsufficientBalance);
+
+    let (_, bump) = Pubkey::find_program_address(&[FEE_VAULT_SEED], ctx.program_id);
+
+    invoke_signed(
+        &system_instruction::transfer(&accts.fee_vault.key(), &accts.authority.key(), amount),
+        &[
+            accts.fee_vault.clone(),
+            accts.authority.to_account_info().clone(),
+            accts.system_program.to_account_info().clone(),
+        ],
+        &[&[FEE_VAULT_SEED, &[bump]]],
+    )?;
+
+    emit!(FeeWithdrawn {
+        authority: accts.authority.key(),
+        amount,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitializePlatform<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        seeds = [PLATFORM_SEED],
+        bump,
+        space = 8 + size_of::<Platform>()
+    )]
+    pub platform: Box<Account<'info, Platform>>,
+
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED],
+        bump
+    )]
+    pub fee_vault: AccountInfo<'info>,
+
+    // only the upgrade authority of the program can set up the platform
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::Presale>,
+
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(authority.key())
+            @ PresaleError::InvalidPlatformAuthority
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ManagePlatform<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PLATFORM_SEED],
+        bump
+    )]
+    pub platform: Box<Account<'info, Platform>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFee<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [PLATFORM_SEED],
+        bump
+    )]
+    pub platform: Box<Account<'info, Platform>>,
+
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED],
+        bump
+    )]
+    pub fee_vault: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
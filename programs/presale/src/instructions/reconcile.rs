@@ -0,0 +1,61 @@
+use anchor_lang::prelude::*;
+
+use crate::{state::*, constants::*, error::*, events::*};
+use anchor_spl::token_interface::TokenAccount;
+
+pub fn reconcile(ctx: Context<Reconcile>, correct: bool) -> Result<()> {
+    let accts = ctx.accounts;
+
+    accts.presale.require_state(&[
+        SaleState::Draft,
+        SaleState::Private,
+        SaleState::Public,
+        SaleState::Paused,
+        SaleState::Ended,
+        SaleState::Finalized,
+        SaleState::Cancelled
+    ])?;
+    let token_expected = accts.presale.expected_token_balance()?;
+    let token_actual = accts.token_vault_account.amount;
+    let sol_expected = accts.presale.expected_sol_balance()?;
+    let sol_actual = accts.vault.lamports();
+    msg!("token expected {:?}, actual {:?}", token_expected, token_actual);
+    msg!("sol expected {:?}, actual {:?}", sol_expected, sol_actual);
+
+    if correct {
+        require!(accts.authority.key() == accts.presale.owner, PresaleError::InvalidOwner);
+
+        accts.presale.reconcile_balances(token_actual, sol_actual)?;
+    }
+
+    emit!(VaultReconciled {
+        token_expected,
+        token_actual,
+        sol_expected,
+        sol_actual,
+        corrected: correct,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct Reconcile<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut, 
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        seeds = [VAULT_SEED],
+        bump
+    )]
+    pub vault: AccountInfo<'info>,
+
+    #[account(address = presale.token_vault)]
+    pub token_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+}
@@ -0,0 +1,144 @@
+use anchor_lang::prelude::*;
+
+use crate::{state::*, constants::*, error::*, events::*, instructions::send_tokens, math::SafeMath};
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{ Mint, TokenAccount, TokenInterface }
+};
+use std::mem::size_of;
+
+pub fn register_referrer(ctx: Context<RegisterReferrer>, code: [u8; 8]) -> Result<()> {
+    let accts = ctx.accounts;
+
+    // referred users can't refer others, so referrals never chain
+    require!(accts.user_info.referrer == Pubkey::default(), PresaleError::ReferralChain);
+    require!(!accts.user_info.is_referrer, PresaleError::AlreadyInitialized);
+
+    accts.user_info.user = accts.owner.key();
+    accts.user_info.is_referrer = true;
+
+    accts.referrer.owner = accts.owner.key();
+    accts.referrer.code = code;
+
+    Ok(())
+}
+
+pub fn claim_referral_reward<'info>(ctx: Context<'_, '_, '_, 'info, ClaimReferralReward<'info>>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.referrer.owner == accts.owner.key(), PresaleError::InvalidOwner);
+    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+
+    // sol rewards are held by the referrer account itself
+    let sol_amount = accts.referrer.sol_claimable;
+    if sol_amount != 0 {
+        accts.referrer.sol_claimable = 0;
+        let referrer_info = accts.referrer.to_account_info();
+        let owner_info = accts.owner.to_account_info();
+        let referrer_lamports = referrer_info.lamports().safe_sub(sol_amount)?;
+        let owner_lamports = owner_info.lamports().safe_add(sol_amount)?;
+        **referrer_info.try_borrow_mut_lamports()? = referrer_lamports;
+        **owner_info.try_borrow_mut_lamports()? = owner_lamports;
+    }
+
+    // token rewards are locked in the token vault, cancelling the sale returned them to the inventory
+    let mut token_amount = accts.referrer.token_claimable;
+    accts.referrer.token_claimable = 0;
+    if accts.presale.state == SaleState::Cancelled {
+        msg!("token reward forfeited {:?}", token_amount);
+        token_amount = 0;
+    }
+    if token_amount != 0 {
+        accts.presale.referral_token_amount = accts.presale.referral_token_amount.safe_sub(token_amount)?;
+
+        send_tokens(
+            &mut accts.presale,
+            &accts.token_mint,
+            &accts.token_vault_account,
+            &accts.token_account,
+            &accts.token_program,
+            ctx.remaining_accounts,
+            token_amount
+        )?;
+    }
+
+    emit!(ReferralClaimed {
+        referrer: accts.referrer.key(),
+        owner: accts.owner.key(),
+        sol_amount,
+        token_amount,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(code: [u8; 8])]
+pub struct RegisterReferrer<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        seeds = [USER_INFO_SEED, owner.key().as_ref()],
+        bump,
+        space = 8 + size_of::<UserInfo>()
+    )]
+    pub user_info: Account<'info, UserInfo>,
+
+    #[account(
+        init,
+        payer = owner,
+        seeds = [REFERRER_SEED, code.as_ref()],
+        bump,
+        space = 8 + size_of::<Referrer>()
+    )]
+    pub referrer: Account<'info, Referrer>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralReward<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [REFERRER_SEED, referrer.code.as_ref()],
+        bump
+    )]
+    pub referrer: Account<'info, Referrer>,
+
+    #[account(
+        mut,
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+
+    #[account(mut)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = token_mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program
+    )]
+    pub token_account: Box<InterfaceAccount<'info, TokenAccount>>, // the token account of referrer
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = presale,
+        token::token_program = token_program
+    )]
+    pub token_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
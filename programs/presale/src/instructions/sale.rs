@@ -1,92 +1,221 @@
 use anchor_lang::prelude::*;
 
-use crate::{state::*, constants::*, error::*};
+use crate::{
+    state::*, constants::*, error::*, events::*,
+    instructions::{reclaim_tokens, send_tokens},
+    math::{checked, SafeMath},
+};
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token::{ self, Mint, Token, TokenAccount, Transfer }
+    token_interface::{ Mint, TokenAccount, TokenInterface }
 };
-use solana_program::{program::invoke, system_instruction};
+use solana_program::{program::{invoke, invoke_signed}, system_instruction, sysvar};
 use std::mem::size_of;
 
-pub fn token_sale(ctx: Context<SaleManagement>, amount: u64, staked_period:u8) -> Result<()> {
+pub fn token_sale<'info>(
+    ctx: Context<'_, '_, '_, 'info, TokenSale<'info>>,
+    mode: PurchaseMode,
+    amount: u64,
+    staked_period: u8,
+    max_sol_amount: u64,
+    min_token_amount: u64,
+    voucher: Option<Voucher>
+) -> Result<()> {
     let accts = ctx.accounts;
 
-    require!(accts.presale.status, PresaleError::NotLive);
+    accts.presale.require_state(&[SaleState::Private, SaleState::Public])?;
     require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+    require!(accts.presale.overflow_token_amount == 0, PresaleError::OverflowSale);
+    require!(!accts.presale.lottery.is_active(), PresaleError::LotterySale);
 
-    let sale_type = accts.presale.sale_type;
+    if accts.presale.auction.is_active() {
+        // bids pay the auction price, a voucher would only burn its nonce
+        require!(voucher.is_none(), PresaleError::InvalidVoucher);
+        return auction_bid(accts, mode, amount, max_sol_amount, min_token_amount);
+    }
 
-    if sale_type {
-        // send token from token vault account to user's token account
-        require!(amount < accts.presale.token_amount, PresaleError::InsufficientBalance);
-        accts.presale.token_amount -= amount;
-        msg!("token amount {:?}", amount);
-
-        // send token from token vault account to user's token account
-        let (_, bump) = Pubkey::find_program_address(&[PRESALE_SEED], ctx.program_id);
-        let vault_seeds = &[PRESALE_SEED, &[bump]];
-        let signer = &[&vault_seeds[..]];
-
-        // Transfer tokens from bridge to receiver
-        let cpi_accounts = Transfer {
-            from: accts.token_vault_account.to_account_info(),
-            to: accts.token_account.to_account_info(),
-            authority: accts.presale.to_account_info(),
-        };
-
-        let cpi_context = CpiContext::new(accts.token_program.to_account_info(), cpi_accounts);
-        token::transfer(cpi_context.with_signer(signer), amount)?;
-        // calculate the sol amount
-        let decimal = accts.token_mint.decimals;
-        msg!("This token's decimal is {:?}", decimal.clone());
-
-        let token_price = accts.presale.token_price + (accts.presale.rate as u128 * amount as u128 /  10u64.pow(decimal.into()) as u128 / 2) as u64;
-        msg!("token_price {:?}", token_price);
-        accts.presale.token_price = token_price;
-
-        let sol_amount = (token_price as u128 * amount as u128 / 10u64.pow(decimal.into()) as u128) as u64;
-        msg!("sol amount {:?}", sol_amount);
-
-
-         // Send sol to the vault
-         invoke(
-            &system_instruction::transfer(
-                &accts.user.key(),
-                &accts.vault.key(),
-                sol_amount
-            ),
-            &[
-                accts.user.to_account_info().clone(),
-                accts.vault.clone(),
-                accts.system_program.to_account_info().clone(),
-            ],
-        )?;
-        accts.presale.sol_amount += sol_amount;
+    let sale_type = accts.presale.state == SaleState::Public;
+
+    // calculate the token and sol amount
+    let decimal = accts.token_mint.decimals;
+    msg!("This token's decimal is {:?}", decimal.clone());
+
+    let current_timestamp = Clock::get()?.unix_timestamp;
+
+    let mut quote = match voucher {
+        Some(voucher) => {
+            // a voucher signed off-chain replaces the sale price for its buyer
+            require!(accts.presale.voucher_signer != Pubkey::default(), PresaleError::InvalidVoucher);
+            let instructions = accts.instructions.as_ref().ok_or(PresaleError::InvalidVoucher)?;
+            voucher.verify(instructions, &accts.presale.voucher_signer, &accts.presale.key())?;
+            require!(voucher.buyer == accts.user.key(), PresaleError::InvalidVoucher);
+            require!(current_timestamp < voucher.expiry, PresaleError::VoucherExpired);
+
+            // the nonce account can only be created once
+            let voucher_nonce = accts.voucher_nonce.as_mut().ok_or(PresaleError::InvalidVoucher)?;
+            voucher_nonce.buyer = accts.user.key();
+            voucher_nonce.nonce = voucher.nonce;
+
+            let quote = quote_at(voucher.price, 0, mode, amount, decimal)?;
+            require!(quote.sol_amount <= voucher.max_amount, PresaleError::VoucherAmountExceeded);
+            quote
+        },
+        None => accts.presale.quote(sale_type, mode, amount, decimal)?,
+    };
+    msg!("token amount {:?}", quote.token_amount);
+    msg!("sol amount {:?}", quote.sol_amount);
+    msg!("token_price {:?}", quote.token_price);
+
+    // the gated private sale is limited to the holders' allocation,
+    // and the whitelisted one to the guaranteed and FCFS rounds
+    if !sale_type {
+        if let Some(holding) = accts.presale.gate_holding(&accts.user.key(), ctx.remaining_accounts)? {
+            let allocation_info = ctx.remaining_accounts.get(holding.allocation_index).ok_or(PresaleError::NotTokenHolder)?;
+            spend_gate_allocation(&holding, allocation_info, &accts.user, &accts.system_program, quote.sol_amount)?;
+        }
+        if accts.presale.has_whitelist() {
+            accts.presale.check_whitelist(&accts.user_info, quote.sol_amount, current_timestamp)?;
+        }
+        accts.user_info.private_sol_paid = accts.user_info.private_sol_paid.safe_add(quote.sol_amount)?;
+        accts.presale.private_sol_sold = accts.presale.private_sol_sold.safe_add(quote.sol_amount)?;
+    }
+
+    // early and large buyers get bonus tokens on top of the purchase
+    accts.presale.add_bonuses(&mut quote, &accts.user_info, current_timestamp)?;
+    let bonus_amount = quote.bonus_amount;
+    let volume_bonus_amount = quote.volume_bonus_amount;
+    msg!("bonus amount {:?}", bonus_amount);
+    msg!("volume bonus amount {:?}", volume_bonus_amount);
+
+    accts.user_info.sol_paid = accts.user_info.sol_paid.safe_add(quote.sol_amount)?;
+    accts.user_info.volume_token_amount = accts.user_info.volume_token_amount.safe_add(quote.token_amount)?;
+    accts.user_info.volume_bonus_paid = accts.user_info.volume_bonus_paid.safe_add(volume_bonus_amount)?;
+
+    let token_amount = quote.token_amount.safe_add(bonus_amount)?.safe_add(volume_bonus_amount)?;
+
+    // staked tokens lock their yield in the vault as well
+    let locked_amount = if sale_type {
+        token_amount
     } else {
-        // Send sol to the vault
+        staking_payout(staked_period, token_amount)?
+    };
+
+    // the price moved since the buyer's quote
+    require!(token_amount >= min_token_amount, PresaleError::SlippageExceeded);
+    require!(quote.sol_amount <= max_sol_amount, PresaleError::SlippageExceeded);
+
+    // the platform fee is taken out of the payment, the rest is raised by the presale
+    let fee_amount = accts.platform.fee_on(quote.sol_amount)?;
+    let sol_amount = quote.sol_amount.safe_sub(fee_amount)?;
+    msg!("fee amount {:?}", fee_amount);
+
+    // the referrer's reward comes out of the presale's share
+    let (referral_sol, referral_token) = match accts.referrer.as_ref() {
+        Some(referrer) => {
+            referrer.check_referral(&referrer.key(), &accts.user.key(), &accts.user_info)?;
+            accts.presale.referral_split(sol_amount, quote.token_amount)?
+        },
+        None => (0, 0),
+    };
+    let sol_amount = sol_amount.safe_sub(referral_sol)?;
+
+    let reserved_amount = locked_amount.safe_add(referral_token)?;
+    require!(reserved_amount < accts.presale.token_amount, PresaleError::InsufficientBalance);
+
+    accts.presale.token_amount = accts.presale.token_amount.safe_sub(reserved_amount)?;
+    accts.presale.referral_token_amount = accts.presale.referral_token_amount.safe_add(referral_token)?;
+    // voucher prices are negotiated per buyer and don't move the sale price
+    if voucher.is_none() {
+        accts.presale.token_price = quote.token_price;
+    }
+    accts.presale.total_token_sold = accts.presale.total_token_sold.safe_add(token_amount)?;
+    accts.presale.total_sol_raised = accts.presale.total_sol_raised.safe_add(sol_amount)?;
+
+    if fee_amount != 0 {
+        // Send the fee to the platform fee vault
         invoke(
             &system_instruction::transfer(
                 &accts.user.key(),
-                &accts.vault.key(),
-                amount
+                &accts.fee_vault.key(),
+                fee_amount
             ),
             &[
                 accts.user.to_account_info().clone(),
-                accts.vault.clone(),
+                accts.fee_vault.clone(),
                 accts.system_program.to_account_info().clone(),
             ],
         )?;
-        accts.presale.sol_amount += amount;
+        accts.platform.total_fee_collected = accts.platform.total_fee_collected.safe_add(fee_amount)?;
+
+        emit!(FeeCollected {
+            user: accts.user.key(),
+            amount: fee_amount,
+        });
+    }
 
-        let decimal = accts.token_mint.decimals;
-        msg!("This token's decimal is {:?}", decimal.clone());
-        
-        let token_amount = (amount as u128 * 10u64.pow(decimal.into()) as u128 / accts.presale.token_price as u128) as u64;
+    // Send sol to the vault
+    invoke(
+        &system_instruction::transfer(
+            &accts.user.key(),
+            &accts.vault.key(),
+            sol_amount
+        ),
+        &[
+            accts.user.to_account_info().clone(),
+            accts.vault.clone(),
+            accts.system_program.to_account_info().clone(),
+        ],
+    )?;
+    accts.presale.sol_amount = accts.presale.sol_amount.safe_add(sol_amount)?;
+    accts.user_info.sol_contributed = accts.user_info.sol_contributed.safe_add(sol_amount)?;
+
+    if let Some(referrer) = accts.referrer.as_mut() {
+        if referral_sol != 0 {
+            // Send the sol reward to the referrer account, it is held there until claimed
+            invoke(
+                &system_instruction::transfer(
+                    &accts.user.key(),
+                    &referrer.key(),
+                    referral_sol
+                ),
+                &[
+                    accts.user.to_account_info().clone(),
+                    referrer.to_account_info().clone(),
+                    accts.system_program.to_account_info().clone(),
+                ],
+            )?;
+        }
 
-        require!(token_amount < accts.presale.token_amount, PresaleError::InsufficientBalance);
-        accts.presale.token_amount -= token_amount;
+        referrer.referral_count = referrer.referral_count.safe_add(1)?;
+        referrer.referred_sol = referrer.referred_sol.safe_add(quote.sol_amount)?;
+        referrer.total_sol_earned = referrer.total_sol_earned.safe_add(referral_sol)?;
+        referrer.sol_claimable = referrer.sol_claimable.safe_add(referral_sol)?;
+        referrer.total_token_earned = referrer.total_token_earned.safe_add(referral_token)?;
+        referrer.token_claimable = referrer.token_claimable.safe_add(referral_token)?;
+        accts.user_info.referrer = referrer.key();
+
+        emit!(ReferralRewarded {
+            referrer: referrer.key(),
+            user: accts.user.key(),
+            sol_amount: referral_sol,
+            token_amount: referral_token,
+        });
+    }
 
-        let current_timestamp = Clock::get()?.unix_timestamp;
+    if sale_type {
+        // send the tokens to the user's token account
+        let received = send_tokens(
+            &mut accts.presale,
+            &accts.token_mint,
+            &accts.token_vault_account,
+            &accts.token_account,
+            &accts.token_program,
+            ctx.remaining_accounts,
+            token_amount
+        )?;
+        accts.user_info.token_received = accts.user_info.token_received.safe_add(received)?;
+    } else {
+        accts.presale.staked_amount = accts.presale.staked_amount.safe_add(locked_amount)?;
 
         match staked_period {
             3_u8 => {
@@ -120,77 +249,440 @@ pub fn token_sale(ctx: Context<SaleManagement>, amount: u64, staked_period:u8) -
         }
     }
 
+    emit!(TokenPurchased {
+        user: accts.user.key(),
+        state: accts.presale.state,
+        token_amount,
+        bonus_amount,
+        volume_bonus_amount,
+        sol_amount: quote.sol_amount,
+        fee_amount,
+        token_price: quote.token_price,
+    });
+
     Ok(())
 }
 
 
-pub fn claim_staked_token(ctx: Context<SaleManagement>, staked_period:u8) -> Result<()> {
+pub fn claim_staked_token<'info>(ctx: Context<'_, '_, '_, 'info, SaleManagement<'info>>, staked_period:u8) -> Result<()> {
     let accts = ctx.accounts;
 
-    require!(accts.presale.status, PresaleError::NotLive);
+    // stakes stay claimable after the sale is over
+    accts.presale.require_state(&[SaleState::Private, SaleState::Public, SaleState::Ended, SaleState::Finalized])?;
     let current_timestamp = Clock::get()?.unix_timestamp;
-    let mut token_amount = 0;
+    let token_amount;
 
     match staked_period {
         3_u8 => {
             require!(accts.user_info.stake_status_3m, PresaleError::NotStaking);
-            require!(current_timestamp  - accts.user_info.stake_start_time_3m >  3 * 30 * 3600 * 24, PresaleError::NotStaking);
+            require!(current_timestamp.safe_sub(accts.user_info.stake_start_time_3m)? >  3 * 30 * 3600 * 24, PresaleError::NotStaking);
             require!(accts.user_info.stake_amount_3m != 0, PresaleError::AlreadyClaim);
 
-            token_amount = accts.user_info.stake_amount_3m * 105 / 100;
+            token_amount = staking_payout(staked_period, accts.user_info.stake_amount_3m)?;
             accts.user_info.stake_amount_3m = 0;
             accts.user_info.stake_start_time_3m = current_timestamp;
         },
         6_u8 => {
             // Logic for 6-month staking period
             require!(accts.user_info.stake_status_6m, PresaleError::NotStaking);
-            require!(current_timestamp  - accts.user_info.stake_start_time_3m > 6 * 30 * 3600 * 24, PresaleError::NotStaking);
+            require!(current_timestamp.safe_sub(accts.user_info.stake_start_time_6m)? > 6 * 30 * 3600 * 24, PresaleError::NotStaking);
             require!(accts.user_info.stake_amount_6m != 0, PresaleError::AlreadyClaim);
 
-            token_amount = accts.user_info.stake_amount_3m * 110 / 100;
+            token_amount = staking_payout(staked_period, accts.user_info.stake_amount_6m)?;
             accts.user_info.stake_amount_6m = 0;
             accts.user_info.stake_start_time_6m = current_timestamp;
         }, 
         9_u8 => {
             // Logic for 9-month staking period
             require!(accts.user_info.stake_status_9m, PresaleError::NotStaking);
-            require!(current_timestamp  - accts.user_info.stake_start_time_3m > 9 * 30 * 3600 * 24, PresaleError::NotStaking);
+            require!(current_timestamp.safe_sub(accts.user_info.stake_start_time_9m)? > 9 * 30 * 3600 * 24, PresaleError::NotStaking);
             require!(accts.user_info.stake_amount_9m != 0, PresaleError::AlreadyClaim);
 
-            token_amount = accts.user_info.stake_amount_3m * 115 / 100;
+            token_amount = staking_payout(staked_period, accts.user_info.stake_amount_9m)?;
             accts.user_info.stake_amount_9m = 0;
             accts.user_info.stake_start_time_9m = current_timestamp;
         },
         12_u8 => {
             // Logic for 12-month staking period
             require!(accts.user_info.stake_status_12m, PresaleError::NotStaking);
-            require!(current_timestamp  - accts.user_info.stake_start_time_3m > 12 * 30 * 3600 * 24, PresaleError::NotStaking);
+            require!(current_timestamp.safe_sub(accts.user_info.stake_start_time_12m)? > 12 * 30 * 3600 * 24, PresaleError::NotStaking);
             require!(accts.user_info.stake_amount_12m != 0, PresaleError::AlreadyClaim);
 
-            token_amount = accts.user_info.stake_amount_3m * 120 / 100;
+            token_amount = staking_payout(staked_period, accts.user_info.stake_amount_12m)?;
             accts.user_info.stake_amount_12m = 0;
             accts.user_info.stake_start_time_12m = current_timestamp;
         },
         _ => return Err(PresaleError::InvalidStakingPeriod.into()), // Handle unsupported values
     }
+    accts.presale.staked_amount = accts.presale.staked_amount.safe_sub(token_amount)?;
+
+    // send the tokens to the user's token account
+    let received = send_tokens(
+        &mut accts.presale,
+        &accts.token_mint,
+        &accts.token_vault_account,
+        &accts.token_account,
+        &accts.token_program,
+        ctx.remaining_accounts,
+        token_amount
+    )?;
+    accts.user_info.token_received = accts.user_info.token_received.safe_add(received)?;
+
+    Ok(())
+}
+
+pub fn refund<'info>(ctx: Context<'_, '_, '_, 'info, SaleManagement<'info>>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    accts.presale.require_state(&[SaleState::Cancelled])?;
+    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+
+    // unclaimed overflow commitments, auction bids and lottery tickets are sent back whole before the
+    // settlement, after it the kept part is refunded pro-rata like any contribution
+    let mut excess_sol = 0;
+    let closes_claim = accts.user_info.committed != 0 || accts.user_info.auction_token_amount != 0;
+    // tokens bought but never sent to the user, unclaimed stakes went back to the inventory on cancel
+    let mut token_unsent = accts.user_info.stake_amount_3m
+        .safe_add(accts.user_info.stake_amount_6m)?
+        .safe_add(accts.user_info.stake_amount_9m)?
+        .safe_add(accts.user_info.stake_amount_12m)?;
+    if accts.user_info.committed != 0 {
+        if accts.presale.overflow_settled {
+            let allocation = accts.presale.overflow_allocation(accts.user_info.committed)?;
+            accts.user_info.sol_contributed = accts.user_info.sol_contributed.safe_add(allocation.sol_amount)?;
+            accts.presale.token_amount = accts.presale.token_amount.safe_add(allocation.token_amount)?;
+            accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_sub(allocation.token_amount)?;
+            token_unsent = token_unsent.safe_add(allocation.token_amount)?;
+            excess_sol = excess_sol.safe_add(allocation.excess_sol)?;
+        } else {
+            excess_sol = excess_sol.safe_add(accts.user_info.committed)?;
+        }
+        accts.user_info.committed = 0;
+    }
+    if accts.user_info.auction_token_amount != 0 {
+        if accts.presale.auction.settled {
+            let allocation = accts.presale.auction.allocation(
+                accts.user_info.auction_token_amount,
+                accts.user_info.auction_sol_paid
+            )?;
+            accts.user_info.sol_contributed = accts.user_info.sol_contributed.safe_add(allocation.sol_amount)?;
+            accts.presale.token_amount = accts.presale.token_amount.safe_add(allocation.token_amount)?;
+            accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_sub(allocation.token_amount)?;
+            token_unsent = token_unsent.safe_add(allocation.token_amount)?;
+            excess_sol = excess_sol.safe_add(allocation.excess_sol)?;
+        } else {
+            excess_sol = excess_sol.safe_add(accts.user_info.auction_sol_paid)?;
+        }
+        accts.user_info.auction_token_amount = 0;
+        accts.user_info.auction_sol_paid = 0;
+    }
+    let mut bond_share = 0;
+    if accts.user_info.lottery_ticket_count != 0 {
+        // the tickets share what was slashed from the owner's bond
+        bond_share = accts.presale.lottery.pay_bond_share(accts.user_info.lottery_ticket_count)?;
+        let lottery = accts.presale.lottery;
+        if lottery.revealed {
+            let allocation = lottery.allocation(accts.user_info.lottery_first_ticket, accts.user_info.lottery_ticket_count)?;
+            accts.user_info.sol_contributed = accts.user_info.sol_contributed.safe_add(allocation.sol_amount)?;
+            accts.presale.token_amount = accts.presale.token_amount.safe_add(allocation.token_amount)?;
+            accts.presale.reserved_token_amount = accts.presale.reserved_token_amount.safe_sub(allocation.token_amount)?;
+            token_unsent = token_unsent.safe_add(allocation.token_amount)?;
+            excess_sol = excess_sol.safe_add(allocation.excess_sol)?;
+        } else {
+            excess_sol = excess_sol.safe_add(accts.user_info.lottery_ticket_count.safe_mul(lottery.sol_per_ticket)?)?;
+        }
+        accts.user_info.lottery_ticket_count = 0;
+    }
+    require!(accts.user_info.sol_contributed != 0 || excess_sol != 0, PresaleError::InsufficientBalance);
+    accts.presale.reserved_sol_amount = accts.presale.reserved_sol_amount.safe_sub(excess_sol)?;
+    if closes_claim {
+        accts.presale.close_claim()?;
+    }
+
+    // the tokens the user still holds go back to the inventory
+    let token_amount = accts.user_info.token_received.min(accts.token_account.amount);
+    if token_amount != 0 {
+        let returned = reclaim_tokens(
+            &mut accts.presale,
+            &accts.token_mint,
+            &accts.token_vault_account,
+            &accts.token_account,
+            &accts.user,
+            &accts.token_program,
+            ctx.remaining_accounts,
+            token_amount
+        )?;
+        accts.presale.token_amount = accts.presale.token_amount.safe_add(returned)?;
+    }
 
-    // send token from token vault account to user's token account
-    let (_, bump) = Pubkey::find_program_address(&[PRESALE_SEED], ctx.program_id);
-    let vault_seeds = &[PRESALE_SEED, &[bump]];
-    let signer = &[&vault_seeds[..]];
+    // pro-rata share of the sol left in the vault when the sale was cancelled, less the share
+    // of the tokens the user kept
+    let sol_amount = accts.presale.refund_amount(
+        accts.user_info.sol_contributed,
+        accts.user_info.token_received.safe_add(token_unsent)?,
+        accts.user_info.token_received.safe_sub(token_amount)?
+    )?;
+    msg!("refund sol amount {:?}", sol_amount);
+
+    // unclaimed stakes were returned to the inventory on cancel
+    accts.user_info.set_inner(UserInfo {
+        user: accts.user.key(),
+        referrer: accts.user_info.referrer,
+        is_referrer: accts.user_info.is_referrer,
+        ..Default::default()
+    });
+
+    let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], ctx.program_id);
+
+    invoke_signed(
+        &system_instruction::transfer(&accts.vault.key(), &accts.user.key(), sol_amount.safe_add(excess_sol)?.safe_add(bond_share)?),
+        &[
+            accts.vault.clone(),
+            accts.user.to_account_info().clone(),
+            accts.system_program.to_account_info().clone(),
+        ],
+        &[&[VAULT_SEED, &[bump]]],
+    )?;
+    accts.presale.sol_amount = accts.presale.sol_amount.safe_sub(sol_amount)?;
 
-    // Transfer tokens from bridge to receiver
-    let cpi_accounts = Transfer {
-        from: accts.token_vault_account.to_account_info(),
-        to: accts.token_account.to_account_info(),
-        authority: accts.presale.to_account_info(),
+    Ok(())
+}
+
+// records `sol_amount` spent with a gate holding, creating its allocation account on first use
+fn spend_gate_allocation<'info>(
+    holding: &GateHolding,
+    allocation_info: &AccountInfo<'info>,
+    user: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    sol_amount: u64
+) -> Result<()> {
+    let (allocation_key, bump) = Pubkey::find_program_address(&[GATE_ALLOCATION_SEED, holding.key.as_ref()], &crate::ID);
+    require!(allocation_info.key() == allocation_key, PresaleError::NotTokenHolder);
+
+    let mut gate_allocation = if allocation_info.owner == &crate::ID {
+        GateAllocation::try_deserialize(&mut &allocation_info.data.borrow()[..])?
+    } else {
+        let space = 8 + size_of::<GateAllocation>();
+        let rent = Rent::get()?.minimum_balance(space).saturating_sub(allocation_info.lamports());
+        if rent != 0 {
+            invoke(
+                &system_instruction::transfer(&user.key(), &allocation_key, rent),
+                &[
+                    user.to_account_info().clone(),
+                    allocation_info.clone(),
+                    system_program.to_account_info().clone(),
+                ],
+            )?;
+        }
+        let signer_seeds: &[&[u8]] = &[GATE_ALLOCATION_SEED, holding.key.as_ref(), &[bump]];
+        invoke_signed(
+            &system_instruction::allocate(&allocation_key, space as u64),
+            &[allocation_info.clone(), system_program.to_account_info().clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(&allocation_key, &crate::ID),
+            &[allocation_info.clone(), system_program.to_account_info().clone()],
+            &[signer_seeds],
+        )?;
+
+        GateAllocation {
+            holding: holding.key,
+            sol_paid: 0,
+        }
     };
 
-    let cpi_context = CpiContext::new(accts.token_program.to_account_info(), cpi_accounts);
-    token::transfer(cpi_context.with_signer(signer), token_amount)?;
+    gate_allocation.sol_paid = gate_allocation.sol_paid.safe_add(sol_amount)?;
+    require!(gate_allocation.sol_paid <= holding.allocation, PresaleError::AllocationExceeded);
+    gate_allocation.try_serialize(&mut &mut allocation_info.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+// a bid at the current auction price, settled once the auction is over
+fn auction_bid(
+    accts: &mut TokenSale,
+    mode: PurchaseMode,
+    amount: u64,
+    max_sol_amount: u64,
+    min_token_amount: u64
+) -> Result<()> {
+    accts.presale.require_state(&[SaleState::Public])?;
+
+    let current_timestamp = Clock::get()?.unix_timestamp;
+    let auction = &accts.presale.auction;
+    require!(current_timestamp >= auction.start_time && !auction.is_over(current_timestamp), PresaleError::AuctionClosed);
+
+    let quote = accts.presale.auction_quote(mode, amount, current_timestamp)?;
+    let token_price = quote.token_price;
+    let auction = &mut accts.presale.auction;
+    msg!("token amount {:?}", quote.token_amount);
+    msg!("sol amount {:?}", quote.sol_amount);
+    msg!("token_price {:?}", token_price);
+
+    // a bid too small to buy a single token unit would only pay sol
+    require!(quote.token_amount != 0, PresaleError::InsufficientBalance);
+    require!(quote.token_amount <= auction.token_amount.safe_sub(auction.token_sold)?, PresaleError::InsufficientBalance);
+    require!(quote.token_amount >= min_token_amount, PresaleError::SlippageExceeded);
+    require!(quote.sol_amount <= max_sol_amount, PresaleError::SlippageExceeded);
+
+    auction.token_sold = auction.token_sold.safe_add(quote.token_amount)?;
+    auction.clearing_price = token_price;
+
+    // Send sol to the vault, the fee is taken and the difference refunded at settlement
+    invoke(
+        &system_instruction::transfer(
+            &accts.user.key(),
+            &accts.vault.key(),
+            quote.sol_amount
+        ),
+        &[
+            accts.user.to_account_info().clone(),
+            accts.vault.clone(),
+            accts.system_program.to_account_info().clone(),
+        ],
+    )?;
+    if accts.user_info.auction_token_amount == 0 {
+        accts.presale.open_claim()?;
+    }
+    accts.user_info.user = accts.user.key();
+    accts.user_info.auction_token_amount = accts.user_info.auction_token_amount.safe_add(quote.token_amount)?;
+    accts.user_info.auction_sol_paid = accts.user_info.auction_sol_paid.safe_add(quote.sol_amount)?;
+    accts.presale.reserved_sol_amount = accts.presale.reserved_sol_amount.safe_add(quote.sol_amount)?;
+
+    emit!(TokenPurchased {
+        user: accts.user.key(),
+        state: accts.presale.state,
+        token_amount: quote.token_amount,
+        bonus_amount: 0,
+        volume_bonus_amount: 0,
+        sol_amount: quote.sol_amount,
+        fee_amount: 0,
+        token_price,
+    });
 
     Ok(())
 }
+
+// staked tokens plus the yield paid out at the end of the staking period
+pub(crate) fn staking_payout(staked_period: u8, amount: u64) -> Result<u64> {
+    let payout_percent = presale_math::stake_payout_percent(staked_period).ok_or(PresaleError::InvalidStakingPeriod)?;
+    checked(presale_math::stake_payout(amount, payout_percent))
+}
+
+pub fn quote(ctx: Context<GetQuote>, phase: SaleState, mode: PurchaseMode, amount: u64) -> Result<PurchaseQuote> {
+    let accts = ctx.accounts;
+
+    require!(accts.presale.token == accts.token_mint.key(), PresaleError::DisMatchToken);
+    require!(phase == SaleState::Private || phase == SaleState::Public, PresaleError::InvalidSaleState);
+    let sale_type = phase == SaleState::Public;
+
+    // same pricing as token_sale, returned to the caller instead of executed
+    let current_timestamp = Clock::get()?.unix_timestamp;
+    if accts.presale.auction.is_active() {
+        return accts.presale.auction_quote(mode, amount, current_timestamp);
+    }
+    let mut quote = accts.presale.quote(sale_type, mode, amount, accts.token_mint.decimals)?;
+
+    // without the buyer's account the volume bonus is the one of a first purchase
+    let user_info = accts.user_info.as_ref().map_or_else(UserInfo::default, |u| u.clone().into_inner());
+    accts.presale.add_bonuses(&mut quote, &user_info, current_timestamp)?;
+
+    Ok(quote)
+}
+
+#[derive(Accounts)]
+#[instruction(
+    mode: PurchaseMode,
+    amount: u64,
+    staked_period: u8,
+    max_sol_amount: u64,
+    min_token_amount: u64,
+    voucher: Option<Voucher>
+)]
+pub struct TokenSale<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [USER_INFO_SEED, user.key().as_ref()],
+        bump,
+        space = 8 + size_of::<UserInfo>()
+    )]
+    pub user_info: Account<'info, UserInfo>,
+
+    #[account(
+        mut, 
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump
+    )]
+    pub vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [PLATFORM_SEED],
+        bump
+    )]
+    pub platform: Box<Account<'info, Platform>>,
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED],
+        bump
+    )]
+    pub fee_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub referrer: Option<Box<Account<'info, Referrer>>>,
+
+    #[account(mut)]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = token_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program
+    )]
+    pub token_account: Box<InterfaceAccount<'info, TokenAccount>>, // the token account of owner
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = presale,
+        token::token_program = token_program
+    )]
+    pub token_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // one nonce account per voucher, only passed with a voucher
+    #[account(
+        init,
+        payer = user,
+        seeds = [VOUCHER_NONCE_SEED, &voucher.map_or(0, |v| v.nonce).to_le_bytes()],
+        bump,
+        space = 8 + size_of::<VoucherNonce>()
+    )]
+    pub voucher_nonce: Option<Account<'info, VoucherNonce>>,
+
+    /// CHECK: the instructions sysvar, only passed with a voucher
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: Option<AccountInfo<'info>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct SaleManagement<'info> {
     #[account(mut)]
@@ -220,25 +712,39 @@ pub struct SaleManagement<'info> {
     pub vault: AccountInfo<'info>,
 
     #[account(mut)]
-    pub token_mint: Box<Account<'info, Mint>>,
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(
         init_if_needed,
         payer = user,
         associated_token::mint = token_mint,
-        associated_token::authority = user
+        associated_token::authority = user,
+        associated_token::token_program = token_program
     )]
-    pub token_account: Box<Account<'info, TokenAccount>>, // the token account of owner
+    pub token_account: Box<InterfaceAccount<'info, TokenAccount>>, // the token account of owner
 
     #[account(
         mut,
         token::mint = token_mint,
-        token::authority = presale
+        token::authority = presale,
+        token::token_program = token_program
     )]
-    pub token_vault_account: Box<Account<'info, TokenAccount>>,
+    pub token_vault_account: Box<InterfaceAccount<'info, TokenAccount>>,
 
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct GetQuote<'info> {
+    #[account(
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub user_info: Option<Account<'info, UserInfo>>, // the buyer's account, for the volume bonus
+}
@@ -0,0 +1,68 @@
+use anchor_lang::prelude::*;
+
+use crate::{state::*, constants::*, error::*, instructions::ManagePresale, math::SafeMath};
+use solana_program::{program::invoke_signed, system_instruction};
+
+pub fn update_recipients(ctx: Context<ManagePresale>, recipients: [Recipient; MAX_RECIPIENTS]) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+
+    accts.presale.set_recipients(recipients)?;
+
+    Ok(())
+}
+
+pub fn distribute<'info>(ctx: Context<'_, '_, '_, 'info, Distribute<'info>>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.presale.state != SaleState::Cancelled, PresaleError::InvalidSaleState);
+
+    let current_timestamp = Clock::get()?.unix_timestamp;
+    let lamports = accts.vault.lamports();
+    let amount = accts.presale.withdrawable_sol(current_timestamp)?.min(lamports);
+    msg!("distribute sol amount {:?}", amount);
+
+    let shares = accts.presale.revenue_shares(amount)?;
+    // the recipient wallets are passed in the configured order
+    require!(ctx.remaining_accounts.len() == shares.len(), PresaleError::InvalidRecipients);
+
+    let (_, bump) = Pubkey::find_program_address(&[VAULT_SEED], ctx.program_id);
+    for ((recipient, share), wallet) in shares.into_iter().zip(ctx.remaining_accounts.iter()) {
+        require!(recipient == wallet.key(), PresaleError::InvalidRecipients);
+
+        invoke_signed(
+            &system_instruction::transfer(&accts.vault.key(), &wallet.key(), share),
+            &[
+                accts.vault.clone(),
+                wallet.clone(),
+                accts.system_program.to_account_info().clone(),
+            ],
+            &[&[VAULT_SEED, &[bump]]],
+        )?;
+    }
+    accts.presale.sol_amount = accts.presale.sol_amount.safe_sub(amount)?;
+    accts.presale.sol_withdrawn = accts.presale.sol_withdrawn.safe_add(amount)?;
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct Distribute<'info> {
+    #[account(
+        mut,
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+
+    /// CHECK:` doc comment explaining why no checks through types are necessary.
+    #[account(
+        mut,
+        seeds = [VAULT_SEED],
+        bump
+    )]
+    pub vault: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>,
+}
@@ -0,0 +1,125 @@
+use anchor_lang::prelude::*;
+
+use crate::{state::*, constants::*, error::*, math::SafeMath};
+use std::mem::size_of;
+
+pub fn open_tranche_vote(ctx: Context<OpenTrancheVote>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    accts.presale.require_state(&[SaleState::Ended, SaleState::Finalized])?;
+    require!(accts.presale.release_schedule.mode == ReleaseMode::Voted, PresaleError::InvalidReleaseSchedule);
+    require!(accts.presale.vote_end == 0, PresaleError::VoteRunning);
+    // every contributor gets their weight before the first vote
+    accts.presale.require_settled()?;
+
+    let tranche = accts.presale.release_schedule
+        .tranche(accts.presale.tranche_index)
+        .ok_or(PresaleError::NoTrancheLeft)?;
+
+    let current_timestamp = Clock::get()?.unix_timestamp;
+    let unlock_time = accts.presale.ended_at.safe_add(tranche.unlock_delay)?;
+    require!(current_timestamp >= unlock_time, PresaleError::TrancheLocked);
+    // the owner can't keep the escrow locked by never opening the vote
+    if accts.authority.key() != accts.presale.owner {
+        require!(
+            current_timestamp >= unlock_time.safe_add(TRANCHE_VOTE_GRACE_PERIOD)?,
+            PresaleError::InvalidOwner
+        );
+    }
+
+    accts.presale.vote_end = current_timestamp.safe_add(accts.presale.release_schedule.duration)?;
+    accts.presale.votes_for = 0;
+    accts.presale.votes_against = 0;
+    msg!("tranche {:?} vote open until {:?}", accts.presale.tranche_index, accts.presale.vote_end);
+
+    Ok(())
+}
+
+pub fn cast_tranche_vote(ctx: Context<CastTrancheVote>, approve: bool) -> Result<()> {
+    let accts = ctx.accounts;
+
+    let current_timestamp = Clock::get()?.unix_timestamp;
+    require!(current_timestamp < accts.presale.vote_end, PresaleError::VoteNotOpen);
+
+    // the vote is weighted by the sol the user put into the escrow
+    let weight = accts.presale.contribution_of(&accts.user_info)?;
+    require!(weight != 0, PresaleError::InsufficientBalance);
+
+    if approve {
+        accts.presale.votes_for = accts.presale.votes_for.safe_add(weight)?;
+    } else {
+        accts.presale.votes_against = accts.presale.votes_against.safe_add(weight)?;
+    }
+
+    accts.tranche_vote.user = accts.user.key();
+    accts.tranche_vote.tranche = accts.presale.tranche_index;
+    accts.tranche_vote.approve = approve;
+    accts.tranche_vote.weight = weight;
+
+    Ok(())
+}
+
+pub fn settle_tranche_vote(ctx: Context<SettleTrancheVote>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.presale.vote_end != 0, PresaleError::VoteNotOpen);
+    let current_timestamp = Clock::get()?.unix_timestamp;
+    require!(current_timestamp >= accts.presale.vote_end, PresaleError::VoteRunning);
+
+    accts.presale.settle_tranche_vote()?;
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct OpenTrancheVote<'info> {
+    pub authority: Signer<'info>, // the owner, or anyone once the vote is overdue
+
+    #[account(
+        mut,
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+}
+
+#[derive(Accounts)]
+pub struct CastTrancheVote<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [USER_INFO_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub user_info: Account<'info, UserInfo>,
+
+    #[account(
+        mut,
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+
+    // one vote per user and tranche
+    #[account(
+        init,
+        payer = user,
+        seeds = [TRANCHE_VOTE_SEED, &presale.tranche_index.to_le_bytes(), user.key().as_ref()],
+        bump,
+        space = 8 + size_of::<TrancheVote>()
+    )]
+    pub tranche_vote: Account<'info, TrancheVote>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleTrancheVote<'info> {
+    #[account(
+        mut,
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+}
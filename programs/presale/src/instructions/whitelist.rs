@@ -0,0 +1,61 @@
+use anchor_lang::prelude::*;
+
+use crate::{state::*, constants::*, error::*, instructions::ManagePresale};
+use std::mem::size_of;
+
+pub fn update_whitelist(
+    ctx: Context<ManagePresale>,
+    whitelist_root: [u8; 32],
+    guaranteed_end: i64,
+    private_sol_cap: u64
+) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.owner.key() == accts.presale.owner, PresaleError::InvalidOwner);
+    accts.presale.require_state(&[SaleState::Draft, SaleState::Private, SaleState::Paused])?;
+    require!(private_sol_cap >= accts.presale.private_sol_sold, PresaleError::AllocationExceeded);
+
+    accts.presale.whitelist_root = whitelist_root;
+    accts.presale.guaranteed_end = guaranteed_end;
+    accts.presale.private_sol_cap = private_sol_cap;
+
+    Ok(())
+}
+
+pub fn join_whitelist(ctx: Context<JoinWhitelist>, allocation: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+    let accts = ctx.accounts;
+
+    require!(accts.presale.has_whitelist(), PresaleError::NotWhitelisted);
+    let leaf = whitelist_leaf(&accts.user.key(), allocation);
+    require!(verify_proof(&proof, &accts.presale.whitelist_root, leaf), PresaleError::NotWhitelisted);
+
+    accts.user_info.user = accts.user.key();
+    accts.user_info.whitelisted = true;
+    accts.user_info.whitelist_allocation = allocation;
+    accts.user_info.whitelist_root = accts.presale.whitelist_root;
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct JoinWhitelist<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [USER_INFO_SEED, user.key().as_ref()],
+        bump,
+        space = 8 + size_of::<UserInfo>()
+    )]
+    pub user_info: Account<'info, UserInfo>,
+
+    #[account(
+        seeds = [PRESALE_SEED],
+        bump
+    )]
+    pub presale: Box<Account<'info, Presale>>,
+
+    pub system_program: Program<'info, System>,
+}
@@ -1,7 +1,11 @@
 pub mod constants;
 pub mod error;
+pub mod events;
 pub mod instructions;
+pub mod math;
 pub mod state;
+#[cfg(test)]
+mod testing;
 
 use anchor_lang::prelude::*;
 
@@ -15,22 +19,120 @@ declare_id!("BTNaNtGC5sTfNUbusLBRuMViPT2wNBvkzCem5HEDBUMM");
 pub mod presale {
     use super::*;
 
-    pub fn initialize(ctx: Context<Initialize>, amount: u64, token_price: u64) -> Result<()> {
-        instructions::initialize(ctx, amount, token_price)
+    /// Creates the launchpad config. Only the program's upgrade authority can
+    /// call it; it becomes the platform authority collecting `fee_bps` of every
+    /// purchase and funds the rent-exempt reserve of the fee vault.
+    pub fn initialize_platform(ctx: Context<InitializePlatform>, fee_bps: u64) -> Result<()> {
+        instructions::initialize_platform(ctx, fee_bps)
     }
 
-    pub fn toggle_status(ctx: Context<ManagePresale>) -> Result<()> {
-        instructions::toggle_status(ctx)
+    pub fn update_platform(ctx: Context<ManagePlatform>, new_authority: Pubkey, fee_bps: u64) -> Result<()> {
+        instructions::update_platform(ctx, new_authority, fee_bps)
     }
 
-    pub fn update_sale_type(ctx: Context<ManagePresale>) -> Result<()> {
-        instructions::update_sale_type(ctx)
+    pub fn withdraw_fee(ctx: Context<WithdrawFee>, amount: u64) -> Result<()> {
+        instructions::withdraw_fee(ctx, amount)
+    }
+
+    /// Creates the presale. `unsold_token_mode` and `release_schedule` can't
+    /// be changed afterwards.
+    ///
+    /// The sale token can be an SPL Token or a Token-2022 mint. With a
+    /// transfer fee the inventory counts what arrives in the vault. With a
+    /// transfer hook, every instruction moving tokens takes the hook's extra
+    /// accounts as remaining accounts.
+    pub fn initialize<'info>(
+        ctx: Context<'_, '_, '_, 'info, Initialize<'info>>,
+        amount: u64,
+        token_price: u64,
+        unsold_token_mode: UnsoldTokenMode,
+        release_schedule: ReleaseSchedule
+    ) -> Result<()> {
+        instructions::initialize(ctx, amount, token_price, unsold_token_mode, release_schedule)
+    }
+
+    /// Hands the sale over to the mint: the presale PDA, which must already be
+    /// the mint authority, mints exactly what is sold or staked, up to `max_supply`.
+    /// `token_amount` then tracks the remaining mintable supply. Only allowed
+    /// in `Draft` with no deposited inventory.
+    pub fn enable_mint_on_demand(ctx: Context<EnableMintOnDemand>, max_supply: u64) -> Result<()> {
+        instructions::enable_mint_on_demand(ctx, max_supply)
+    }
+
+    /// Moves the sale through its lifecycle, e.g. Draft -> Private -> Public
+    /// -> Ended. Invalid transitions are rejected by `SaleState::can_transition_to`.
+    pub fn update_sale_state(ctx: Context<ManagePresale>, new_state: SaleState) -> Result<()> {
+        instructions::update_sale_state(ctx, new_state)
+    }
+
+    /// Aborts the sale. The raised sol is frozen in the vault and every buyer
+    /// can `refund` their pro-rata share of it. Migrated sales can't be
+    /// cancelled, their earlier buyers have no contribution records.
+    pub fn cancel_sale(ctx: Context<ManagePresale>) -> Result<()> {
+        instructions::cancel_sale(ctx)
     }
 
     pub fn update_token_price(ctx: Context<ManagePresale>, new_price: u64) -> Result<()> {
         instructions::update_token_price(ctx, new_price)
     }
 
+    /// Sets the early-bird bonus windows, counted from the moment the sale
+    /// left Draft. A purchase gets the bonus of the first window still open.
+    pub fn update_early_bird_bonus(ctx: Context<ManagePresale>, windows: [BonusWindow; MAX_BONUS_WINDOWS]) -> Result<()> {
+        instructions::update_early_bird_bonus(ctx, windows)
+    }
+
+    /// Sets the volume bonus tiers. The bonus applies to the cumulative
+    /// purchases of a user, so splitting a buy neither loses nor adds bonus.
+    pub fn update_volume_bonus(ctx: Context<ManagePresale>, tiers: [VolumeTier; MAX_VOLUME_TIERS]) -> Result<()> {
+        instructions::update_volume_bonus(ctx, tiers)
+    }
+
+    /// Sets the referrer's share of referred purchases, paid in sol or tokens.
+    pub fn update_referral(ctx: Context<ManagePresale>, referral_bps: u64, referral_reward: ReferralReward) -> Result<()> {
+        instructions::update_referral(ctx, referral_bps, referral_reward)
+    }
+
+    /// Sets the key signing purchase vouchers, the default key disables them.
+    pub fn update_voucher_signer(ctx: Context<ManagePresale>, voucher_signer: Pubkey) -> Result<()> {
+        instructions::update_voucher_signer(ctx, voucher_signer)
+    }
+
+    /// Restricts the private sale to holders of `gate_mint`, or of any token
+    /// of the `gate_mint` collection. Each holder can spend
+    /// `gate_sol_per_token` lamports per whole token held, up to `gate_max_allocation`.
+    /// The spent allocation is tracked per holder wallet, or per NFT for
+    /// collection gates so it doesn't reset when the NFT changes wallet.
+    pub fn update_token_gate(
+        ctx: Context<ManageTokenGate>,
+        gate_mode: GateMode,
+        gate_sol_per_token: u64,
+        gate_max_allocation: u64
+    ) -> Result<()> {
+        instructions::update_token_gate(ctx, gate_mode, gate_sol_per_token, gate_max_allocation)
+    }
+
+    /// Restricts the private sale to the wallets of the `whitelist_root` merkle tree.
+    /// Until `guaranteed_end` each wallet can spend its guaranteed allocation;
+    /// afterwards the rest of `private_sol_cap` is first-come-first-served
+    /// for every whitelisted wallet. A zeroed root opens the private sale to all.
+    /// A new root drops the entries joined under the previous one.
+    pub fn update_whitelist(
+        ctx: Context<ManagePresale>,
+        whitelist_root: [u8; 32],
+        guaranteed_end: i64,
+        private_sol_cap: u64
+    ) -> Result<()> {
+        instructions::update_whitelist(ctx, whitelist_root, guaranteed_end, private_sol_cap)
+    }
+
+    /// Records the user's whitelist entry from its merkle proof. The leaf is
+    /// `hash(user, allocation)`, pairs are hashed in sorted order. The entry
+    /// is bound to the current root.
+    pub fn join_whitelist(ctx: Context<JoinWhitelist>, allocation: u64, proof: Vec<[u8; 32]>) -> Result<()> {
+        instructions::join_whitelist(ctx, allocation, proof)
+    }
+
     pub fn update_owner(ctx: Context<ManagePresale>, new_owner: Pubkey) -> Result<()> {
         instructions::update_owner(ctx, new_owner)
     }
@@ -39,23 +141,253 @@ pub mod presale {
         instructions::update_rate(ctx, rate)
     }
 
-    pub fn token_sale(ctx: Context<SaleManagement>, amount: u64, staked_period: u8) -> Result<()> {
-        instructions::token_sale(ctx, amount, staked_period)
+    /// Buys presale tokens in the current phase.
+    ///
+    /// With `PurchaseMode::ExactIn` the buyer spends exactly `amount` lamports
+    /// and the token amount is rounded down. With `PurchaseMode::ExactOut` the
+    /// buyer receives exactly `amount` tokens and the lamport cost is rounded up.
+    /// `max_sol_amount` and `min_token_amount` bound the trade against price
+    /// movement; pass `u64::MAX` and `0` to disable them. The platform fee is
+    /// taken out of the lamports paid. The optional `referrer` earns its
+    /// share of the purchase and stays bound to the buyer.
+    ///
+    /// A `voucher` signed by the presale's `voucher_signer` buys at the
+    /// voucher price, up to its `max_amount` lamports. The transaction must
+    /// verify the signature over `Voucher::message` with an ed25519 program
+    /// instruction placed right before this one, and pass the voucher's nonce
+    /// account and the instructions sysvar. Vouchers are refused while a dutch
+    /// auction runs.
+    ///
+    /// In a token-gated private sale the holder token account is passed as
+    /// the first remaining account, followed by its metadata account for
+    /// collection gates and then by the holding's `GateAllocation` account.
+    /// Public purchases pass the transfer hook accounts instead.
+    pub fn token_sale<'info>(
+        ctx: Context<'_, '_, '_, 'info, TokenSale<'info>>,
+        mode: PurchaseMode,
+        amount: u64,
+        staked_period: u8,
+        max_sol_amount: u64,
+        min_token_amount: u64,
+        voucher: Option<Voucher>
+    ) -> Result<()> {
+        instructions::token_sale(ctx, mode, amount, staked_period, max_sol_amount, min_token_amount, voucher)
+    }
+
+    /// Registers a referral code for the signer. Users who were referred
+    /// can't register one.
+    pub fn register_referrer(ctx: Context<RegisterReferrer>, code: [u8; 8]) -> Result<()> {
+        instructions::register_referrer(ctx, code)
+    }
+
+    /// Sends the referrer's sol and token rewards. Token rewards are forfeited
+    /// once the sale is cancelled.
+    pub fn claim_referral_reward<'info>(ctx: Context<'_, '_, '_, 'info, ClaimReferralReward<'info>>) -> Result<()> {
+        instructions::claim_referral_reward(ctx)
+    }
+
+    /// Prices a `token_sale` of `amount` in the given phase (`SaleState::Private`
+    /// or `SaleState::Public`) without mutating any account. The quote
+    /// includes the early-bird bonus and, given the buyer's `user_info`, the
+    /// volume bonus; while a dutch auction runs it prices a bid instead.
+    /// The `PurchaseQuote` is returned through `set_return_data`, so clients
+    /// can read it with `simulateTransaction`.
+    pub fn quote(ctx: Context<GetQuote>, phase: SaleState, mode: PurchaseMode, amount: u64) -> Result<PurchaseQuote> {
+        instructions::quote(ctx, phase, mode, amount)
+    }
+
+    /// Turns the public sale into an overflow sale: `token_amount` tokens are
+    /// set aside and shared pro-rata between the users committing sol until
+    /// `commit_end`. At most `sol_target` lamports are raised, the rest of each
+    /// commitment is sent back on claim.
+    pub fn start_overflow_sale(ctx: Context<ManagePresale>, token_amount: u64, sol_target: u64, commit_end: i64) -> Result<()> {
+        instructions::start_overflow_sale(ctx, token_amount, sol_target, commit_end)
+    }
+
+    /// Commits sol to the overflow sale, without any cap.
+    pub fn commit_sol(ctx: Context<CommitSol>, amount: u64) -> Result<()> {
+        instructions::commit_sol(ctx, amount)
+    }
+
+    /// Settles the overflow sale once the commitment window is over. Anyone can call it.
+    pub fn settle_overflow(ctx: Context<SettleSale>) -> Result<()> {
+        instructions::settle_overflow(ctx)
+    }
+
+    /// Sends the user's share of the settled overflow sale and the excess of their commitment.
+    /// The last claim releases the rounding dust left in reserve, see `Presale::close_claim`.
+    pub fn claim_overflow<'info>(ctx: Context<'_, '_, '_, 'info, SaleManagement<'info>>) -> Result<()> {
+        instructions::claim_overflow(ctx)
+    }
+
+    /// Turns the public sale into a dutch auction of `token_amount` tokens.
+    /// The price decays linearly from `start_price` to `floor_price` between
+    /// `start_time` and `end_time`; bids go through `token_sale`.
+    pub fn start_dutch_auction(
+        ctx: Context<StartDutchAuction>,
+        token_amount: u64,
+        start_price: u64,
+        floor_price: u64,
+        start_time: i64,
+        end_time: i64
+    ) -> Result<()> {
+        instructions::start_dutch_auction(ctx, token_amount, start_price, floor_price, start_time, end_time)
+    }
+
+    /// Settles the auction once it is over or sold out. Every bid clears at the
+    /// price of the last bid of a sold out auction, or at the floor. Anyone can call it.
+    pub fn settle_auction(ctx: Context<SettleSale>) -> Result<()> {
+        instructions::settle_auction(ctx)
+    }
+
+    /// Sends the user's auctioned tokens and refunds what they paid above the clearing price.
+    /// The last claim releases the rounding dust left in reserve, see `Presale::close_claim`.
+    pub fn claim_auction<'info>(ctx: Context<'_, '_, '_, 'info, SaleManagement<'info>>) -> Result<()> {
+        instructions::claim_auction(ctx)
+    }
+
+    /// Turns the public sale into a lottery of `winning_tickets` tickets, each
+    /// worth `token_per_ticket` tokens for `sol_per_ticket` lamports.
+    /// `seed_hash` commits to the seed the winners are drawn from. The owner
+    /// bonds `LOTTERY_BOND_BPS` of the most the lottery can raise until the
+    /// winners are drawn.
+    pub fn start_lottery(
+        ctx: Context<StartLottery>,
+        token_per_ticket: u64,
+        sol_per_ticket: u64,
+        winning_tickets: u64,
+        registration_end: i64,
+        seed_hash: [u8; 32]
+    ) -> Result<()> {
+        instructions::start_lottery(ctx, token_per_ticket, sol_per_ticket, winning_tickets, registration_end, seed_hash)
+    }
+
+    /// Registers up to `MAX_LOTTERY_TICKETS` tickets, once per user.
+    pub fn register_tickets(ctx: Context<CommitSol>, ticket_count: u64) -> Result<()> {
+        instructions::register_tickets(ctx, ticket_count)
+    }
+
+    /// Closes the ticket registration once `registration_end` passed and sets
+    /// the draw slot `LOTTERY_DRAW_DELAY` slots ahead. Anyone can call it.
+    pub fn close_lottery_registration(ctx: Context<CloseLotteryRegistration>) -> Result<()> {
+        instructions::close_lottery_registration(ctx)
+    }
+
+    /// Reveals the committed seed and draws the winners from it and the hash
+    /// of the draw slot, while the `SlotHashes` sysvar still lists that slot,
+    /// and returns the owner's bond. Once the slot is gone it only moves the
+    /// draw slot ahead, for `LOTTERY_REARM_PENALTY_BPS` of the bond.
+    /// It must happen within `LOTTERY_REVEAL_PERIOD`, otherwise the lottery
+    /// ends, every ticket is refunded and the tickets share the whole bond.
+    pub fn reveal_lottery(ctx: Context<RevealLottery>, seed: [u8; 32]) -> Result<()> {
+        instructions::reveal_lottery(ctx, seed)
+    }
+
+    /// Sends the tokens of the user's winning tickets and refunds the losing ones,
+    /// with the tickets' share of the owner's slashed bond.
+    pub fn claim_lottery<'info>(ctx: Context<'_, '_, '_, 'info, SaleManagement<'info>>) -> Result<()> {
+        instructions::claim_lottery(ctx)
+    }
+
+    /// Rewrites the presale account created by an older version of the program
+    /// to the current layout. The owner pays the rent of the bigger account.
+    /// The migrated sale can't be cancelled.
+    pub fn migrate_presale(ctx: Context<MigratePresale>) -> Result<()> {
+        instructions::migrate_presale(ctx)
+    }
+
+    /// Grows a user info account created by an older version of the program
+    /// to the current layout and locks the yield of its stakes, after
+    /// `migrate_presale`. The new fields start zeroed, the user pays the rent.
+    pub fn migrate_user_info(ctx: Context<MigrateUserInfo>) -> Result<()> {
+        instructions::migrate_user_info(ctx)
     }
 
-    pub fn claim_staked_token(ctx: Context<SaleManagement>, staked_period: u8) -> Result<()> {
+    pub fn claim_staked_token<'info>(ctx: Context<'_, '_, '_, 'info, SaleManagement<'info>>, staked_period: u8) -> Result<()> {
         instructions::claim_staked_token(ctx, staked_period)
     }
 
-    pub fn deposit_token(ctx: Context<ManageToken>, amount: u64) -> Result<()> {
+    /// Returns the buyer's contribution from a cancelled sale. Tokens already
+    /// sent to the buyer are pulled back to the vault, as many as the buyer
+    /// still holds, and the refund shrinks with the share of tokens kept.
+    pub fn refund<'info>(ctx: Context<'_, '_, '_, 'info, SaleManagement<'info>>) -> Result<()> {
+        instructions::refund(ctx)
+    }
+
+    /// Opens the contributor vote on the next tranche of a `ReleaseMode::Voted` schedule.
+    /// Anyone can open it once `TRANCHE_VOTE_GRACE_PERIOD` passed since the tranche unlocked.
+    /// The overflow sale, auction and lottery have to be settled first.
+    pub fn open_tranche_vote(ctx: Context<OpenTrancheVote>) -> Result<()> {
+        instructions::open_tranche_vote(ctx)
+    }
+
+    /// Votes on the open tranche, weighted by the sol the user contributed,
+    /// unclaimed overflow, auction and lottery allocations included.
+    pub fn cast_tranche_vote(ctx: Context<CastTrancheVote>, approve: bool) -> Result<()> {
+        instructions::cast_tranche_vote(ctx, approve)
+    }
+
+    /// Closes the tranche vote once the window is over. When at least
+    /// `TRANCHE_VOTE_QUORUM_BPS` of the raised sol voted, the tranche unlocks to
+    /// the owner if the votes for win, and is rejected if the votes against win;
+    /// a rejection cancels the sale so contributors can `refund` the remaining
+    /// escrow. Without a quorum or on a tie the tranche stays locked and a new
+    /// vote can be opened.
+    pub fn settle_tranche_vote(ctx: Context<SettleTrancheVote>) -> Result<()> {
+        instructions::settle_tranche_vote(ctx)
+    }
+
+    pub fn deposit_token<'info>(ctx: Context<'_, '_, '_, 'info, ManageToken<'info>>, amount: u64) -> Result<()> {
         instructions::deposit_token(ctx, amount)
     }
 
-    pub fn withdraw_token(ctx: Context<ManageToken>, amount: u64) -> Result<()> {
+    /// Withdraws unsold tokens to the owner. After finalize only the tokens
+    /// kept with `UnsoldTokenMode::KeepForRewards` can be withdrawn. A
+    /// mint-on-demand sale only withdraws donated tokens, in any state.
+    pub fn withdraw_token<'info>(ctx: Context<'_, '_, '_, 'info, ManageToken<'info>>, amount: u64) -> Result<()> {
         instructions::withdraw_token(ctx, amount)
     }
 
+    /// Withdraws raised sol to the owner, limited to the part unlocked by the
+    /// release schedule. Disabled while a revenue split is configured.
     pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
         instructions::withdraw(ctx, amount)
     }
+
+    /// Withdraws sol donated to the vault, as found by `reconcile`, to the owner.
+    /// Donations aren't raised sol, neither the release schedule nor a
+    /// cancel locks them.
+    pub fn withdraw_donated_sol(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
+        instructions::withdraw_donated_sol(ctx, amount)
+    }
+
+    /// Sets the revenue split of the raised sol. Shares are in basis points and
+    /// must add up to 10000; all-empty entries turn the split off.
+    pub fn update_recipients(ctx: Context<ManagePresale>, recipients: [Recipient; MAX_RECIPIENTS]) -> Result<()> {
+        instructions::update_recipients(ctx, recipients)
+    }
+
+    /// Splits the unlocked raised sol between the recipients. Anyone can call
+    /// it; the recipient wallets are passed as writable remaining accounts in
+    /// the configured order.
+    pub fn distribute<'info>(ctx: Context<'_, '_, '_, 'info, Distribute<'info>>) -> Result<()> {
+        instructions::distribute(ctx)
+    }
+
+    /// Compares the books against the actual vault balances and emits a
+    /// `VaultReconciled` event. With `correct`, the owner syncs the books:
+    /// a token surplus joins the inventory, or is tracked as donated when
+    /// minting on demand, and a sol surplus is tracked as donated. A token
+    /// shortfall is taken out of the same books, a sol shortfall out of the
+    /// raised sol.
+    pub fn reconcile(ctx: Context<Reconcile>, correct: bool) -> Result<()> {
+        instructions::reconcile(ctx, correct)
+    }
+
+    /// Permanently ends an `Ended` sale and settles the unsold tokens according to
+    /// the `UnsoldTokenMode` chosen at initialization. Tokens locked for
+    /// outstanding stakes stay in the vault.
+    pub fn finalize<'info>(ctx: Context<'_, '_, '_, 'info, ManageToken<'info>>) -> Result<()> {
+        instructions::finalize(ctx)
+    }
 }
@@ -0,0 +1,111 @@
+use anchor_lang::prelude::*;
+
+use crate::error::PresaleError;
+use presale_math::Rounding;
+
+/// Checked integer arithmetic failing with `MathOverflow`.
+pub trait SafeMath: Sized {
+    fn safe_add(self, rhs: Self) -> Result<Self>;
+    fn safe_sub(self, rhs: Self) -> Result<Self>;
+    fn safe_mul(self, rhs: Self) -> Result<Self>;
+    fn safe_div(self, rhs: Self) -> Result<Self>;
+}
+
+macro_rules! impl_safe_math {
+    ($($t:ty),*) => {$(
+        impl SafeMath for $t {
+            fn safe_add(self, rhs: Self) -> Result<Self> {
+                Ok(self.checked_add(rhs).ok_or(PresaleError::MathOverflow)?)
+            }
+
+            fn safe_sub(self, rhs: Self) -> Result<Self> {
+                Ok(self.checked_sub(rhs).ok_or(PresaleError::MathOverflow)?)
+            }
+
+            fn safe_mul(self, rhs: Self) -> Result<Self> {
+                Ok(self.checked_mul(rhs).ok_or(PresaleError::MathOverflow)?)
+            }
+
+            fn safe_div(self, rhs: Self) -> Result<Self> {
+                Ok(self.checked_div(rhs).ok_or(PresaleError::MathOverflow)?)
+            }
+        }
+    )*};
+}
+
+impl_safe_math!(u8, u64, i64, u128);
+
+/// Narrows to `u64`, failing instead of truncating.
+pub fn to_u64<T: TryInto<u64>>(value: T) -> Result<u64> {
+    Ok(value.try_into().map_err(|_| PresaleError::MathOverflow)?)
+}
+
+/// Maps an overflow of the shared `presale_math` functions to `MathOverflow`.
+pub fn checked<T>(value: Option<T>) -> Result<T> {
+    Ok(value.ok_or(PresaleError::MathOverflow)?)
+}
+
+/// `a * b / c` rounded down.
+pub fn mul_div(a: u64, b: u64, c: u64) -> Result<u64> {
+    checked(presale_math::mul_div(a, b, c, Rounding::Down))
+}
+
+/// `a * b / c` rounded up.
+pub fn mul_div_ceil(a: u64, b: u64, c: u64) -> Result<u64> {
+    checked(presale_math::mul_div(a, b, c, Rounding::Up))
+}
+
+/// `bps` basis points of `amount`, rounded down.
+pub fn bps_of(amount: u64, bps: u64) -> Result<u64> {
+    checked(presale_math::bps_of(amount, bps))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instructions::staking_payout, state::{quote_at, PurchaseMode}};
+
+    fn overflow<T: std::fmt::Debug>(result: Result<T>) {
+        assert_eq!(result.unwrap_err(), PresaleError::MathOverflow.into());
+    }
+
+    #[test]
+    fn overflows_fail_with_math_overflow() {
+        assert_eq!(u64::MAX.safe_sub(1).unwrap(), u64::MAX - 1);
+        overflow(u64::MAX.safe_add(1));
+        overflow(0u64.safe_sub(1));
+        overflow(u64::MAX.safe_mul(2));
+        overflow(1u64.safe_div(0));
+        overflow(i64::MIN.safe_sub(1));
+        overflow(255u8.safe_add(1));
+    }
+
+    #[test]
+    fn casts_fail_instead_of_truncating() {
+        assert_eq!(to_u64(42i64).unwrap(), 42);
+        assert_eq!(to_u64(u64::MAX as u128).unwrap(), u64::MAX);
+        overflow(to_u64(-1i64));
+        overflow(to_u64(u64::MAX as u128 + 1));
+    }
+
+    #[test]
+    fn ratios_are_computed_in_128_bits() {
+        assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX).unwrap(), u64::MAX);
+        assert_eq!(mul_div(10, 10, 3).unwrap(), 33);
+        assert_eq!(mul_div_ceil(10, 10, 3).unwrap(), 34);
+        assert_eq!(bps_of(u64::MAX, 10_000).unwrap(), u64::MAX);
+        overflow(mul_div(u64::MAX, 2, 1));
+        overflow(mul_div(1, 1, 0));
+    }
+
+    #[test]
+    fn large_decimals_and_amounts_overflow_instead_of_panicking() {
+        assert!(quote_at(1_000, 0, PurchaseMode::ExactIn, 1_000_000, 9).is_ok());
+        // 10^20 doesn't fit a u64
+        overflow(quote_at(1_000, 0, PurchaseMode::ExactIn, 1_000_000, 20));
+        overflow(quote_at(u64::MAX, 0, PurchaseMode::ExactOut, u64::MAX, 0));
+        overflow(staking_payout(12, u64::MAX));
+        assert_eq!(staking_payout(7, 100).unwrap_err(), PresaleError::InvalidStakingPeriod.into());
+    }
+}
@@ -0,0 +1,113 @@
+use anchor_lang::prelude::*;
+
+use crate::math::{checked, mul_div, to_u64, SafeMath};
+use presale_math::{lamports_for_tokens, Rounding};
+use crate::state::SettledAllocation;
+
+/// Dutch auction sale: the price decays linearly from `start_price` to
+/// `floor_price` between `start_time` and `end_time`, and every buyer
+/// settles at the clearing price once the auction is over.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct DutchAuction {
+    pub token_amount: u64, // tokens auctioned, 0 when the sale isn't an auction
+    pub decimals: u8, // decimals of the sale token
+    pub start_price: u64, // lamports per token at start_time
+    pub floor_price: u64, // lamports per token from end_time on
+    pub start_time: i64,
+    pub end_time: i64,
+    pub token_sold: u64, // tokens bid for so far
+    pub clearing_price: u64, // price of the last bid, the final price once settled
+    pub sol_raised: u64, // lamports kept by the sale net of the platform fee, once settled
+    pub settled: bool, // the auction was settled and can be claimed
+}
+
+impl DutchAuction {
+    pub fn is_active(&self) -> bool {
+        self.token_amount != 0
+    }
+
+    /// The auction is over at `end_time` or as soon as it sells out.
+    pub fn is_over(&self, now: i64) -> bool {
+        now >= self.end_time || self.token_sold == self.token_amount
+    }
+
+    pub fn price_at(&self, now: i64) -> Result<u64> {
+        if now <= self.start_time {
+            return Ok(self.start_price);
+        }
+        if now >= self.end_time {
+            return Ok(self.floor_price);
+        }
+
+        let elapsed = to_u64(now.safe_sub(self.start_time)?)?;
+        let duration = to_u64(self.end_time.safe_sub(self.start_time)?)?;
+        let decay = mul_div(self.start_price.safe_sub(self.floor_price)?, elapsed, duration)?;
+        self.start_price.safe_sub(decay)
+    }
+
+    /// A buyer's share of the settled auction, for `token_amount` tokens bid with `sol_paid` lamports.
+    pub fn allocation(&self, token_amount: u64, sol_paid: u64) -> Result<SettledAllocation> {
+        // rounded up so the excess sent back never adds up to more than the vault holds
+        let sol_kept = checked(lamports_for_tokens(token_amount, self.clearing_price, self.decimals, Rounding::Up))?;
+
+        Ok(SettledAllocation {
+            token_amount,
+            sol_amount: mul_div(token_amount, self.sol_raised, self.token_sold)?,
+            excess_sol: sol_paid.saturating_sub(sol_kept),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auction() -> DutchAuction {
+        DutchAuction {
+            token_amount: 1_000,
+            decimals: 0,
+            start_price: 100,
+            floor_price: 40,
+            start_time: 1_000,
+            end_time: 1_600,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn price_decays_linearly_to_the_floor() {
+        let auction = auction();
+        assert_eq!(auction.price_at(0).unwrap(), 100);
+        assert_eq!(auction.price_at(1_000).unwrap(), 100);
+        assert_eq!(auction.price_at(1_300).unwrap(), 70);
+        // rounded in favour of the sale
+        assert_eq!(auction.price_at(1_001).unwrap(), 100);
+        assert_eq!(auction.price_at(1_600).unwrap(), 40);
+        assert_eq!(auction.price_at(9_999).unwrap(), 40);
+    }
+
+    #[test]
+    fn auction_ends_at_end_time_or_once_sold_out() {
+        let mut auction = auction();
+        assert!(!auction.is_over(1_599));
+        assert!(auction.is_over(1_600));
+        auction.token_sold = 1_000;
+        assert!(auction.is_over(1_200));
+    }
+
+    #[test]
+    fn bidders_get_the_excess_over_the_clearing_price_back() {
+        // 300 tokens bid for at 90 then 700 at 70, cleared at the last bid
+        let auction = DutchAuction {
+            token_sold: 1_000,
+            clearing_price: 70,
+            sol_raised: 70_000,
+            settled: true,
+            ..auction()
+        };
+        let early = auction.allocation(300, 27_000).unwrap();
+        assert_eq!(early, SettledAllocation { token_amount: 300, sol_amount: 21_000, excess_sol: 6_000 });
+        let late = auction.allocation(700, 49_000).unwrap();
+        assert_eq!(late, SettledAllocation { token_amount: 700, sol_amount: 49_000, excess_sol: 0 });
+    }
+}
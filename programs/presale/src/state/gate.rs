@@ -0,0 +1,144 @@
+use anchor_lang::prelude::*;
+
+use crate::{constants::*, error::*, math::checked, state::Presale};
+use anchor_spl::{token, token_2022, token_interface::TokenAccount};
+use presale_math::{lamports_for_tokens, Rounding};
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum GateMode {
+    /// Anyone can buy in the private sale.
+    #[default]
+    Open,
+    /// Holders of `gate_mint` can buy in the private sale.
+    Mint,
+    /// Holders of any token of the verified collection `gate_mint` can buy in the private sale.
+    Collection,
+}
+
+/// Lamports spent in the gated private sale against one holding. An NFT's
+/// allocation stays spent when it moves to another wallet.
+#[account]
+#[derive(Default)]
+pub struct GateAllocation {
+    pub holding: Pubkey, // the holder wallet in mint mode, the NFT mint in collection mode
+    pub sol_paid: u64, // Lamports paid with this holding, fees included
+}
+
+/// A gate token holding and the lamports it allows in the private sale.
+pub struct GateHolding {
+    pub key: Pubkey, // seeds the holding's `GateAllocation` account
+    pub allocation: u64,
+    pub allocation_index: usize, // position of the `GateAllocation` account in the remaining accounts
+}
+
+// the leading fields of a token metadata account, up to the collection
+#[derive(AnchorDeserialize)]
+struct MetadataPrefix {
+    _key: u8,
+    _update_authority: Pubkey,
+    mint: Pubkey,
+    _name: Vec<u8>,
+    _symbol: Vec<u8>,
+    _uri: Vec<u8>,
+    _seller_fee_basis_points: u16,
+    _creators: Option<Vec<(Pubkey, bool, u8)>>,
+    _primary_sale_happened: bool,
+    _is_mutable: bool,
+    _edition_nonce: Option<u8>,
+    _token_standard: Option<u8>,
+    collection: Option<(bool, Pubkey)>,
+}
+
+impl Presale {
+    /// The gate holding of `user`, from the holder token account (and its
+    /// metadata account in collection mode) passed as remaining accounts.
+    /// An open private sale has none.
+    pub fn gate_holding(&self, user: &Pubkey, accounts: &[AccountInfo]) -> Result<Option<GateHolding>> {
+        if self.gate_mode == GateMode::Open {
+            return Ok(None);
+        }
+
+        let holder_info = accounts.first().ok_or(PresaleError::NotTokenHolder)?;
+        require!(
+            holder_info.owner == &token::ID || holder_info.owner == &token_2022::ID,
+            PresaleError::NotTokenHolder
+        );
+        let holder = TokenAccount::try_deserialize(&mut &holder_info.data.borrow()[..])?;
+        require!(holder.owner == *user, PresaleError::NotTokenHolder);
+        require!(holder.amount != 0, PresaleError::NotTokenHolder);
+
+        // an NFT is tracked by its mint, a fungible holding by the wallet holding it, so
+        // spreading the tokens over several token accounts doesn't add allocations
+        let (key, allocation_index) = match self.gate_mode {
+            GateMode::Open => return Ok(None),
+            GateMode::Mint => {
+                require!(holder.mint == self.gate_mint, PresaleError::NotTokenHolder);
+                (holder.owner, 1)
+            },
+            GateMode::Collection => {
+                let metadata_info = accounts.get(1).ok_or(PresaleError::NotTokenHolder)?;
+                let (metadata_key, _) = Pubkey::find_program_address(
+                    &[b"metadata", TOKEN_METADATA_PROGRAM_ID.as_ref(), holder.mint.as_ref()],
+                    &TOKEN_METADATA_PROGRAM_ID
+                );
+                require!(metadata_info.key() == metadata_key, PresaleError::NotTokenHolder);
+                require!(metadata_info.owner == &TOKEN_METADATA_PROGRAM_ID, PresaleError::NotTokenHolder);
+
+                let metadata = MetadataPrefix::deserialize(&mut &metadata_info.data.borrow()[..])?;
+                require!(metadata.mint == holder.mint, PresaleError::NotTokenHolder);
+                require!(metadata.collection == Some((true, self.gate_mint)), PresaleError::NotTokenHolder);
+                (holder.mint, 2)
+            },
+        };
+
+        // the allocation scales with the holding, up to the per-holder cap
+        let allocation = checked(lamports_for_tokens(holder.amount, self.gate_sol_per_token, self.gate_decimals, Rounding::Down))?;
+        Ok(Some(GateHolding {
+            key,
+            allocation: allocation.min(self.gate_max_allocation),
+            allocation_index,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::token_account_data as token_account;
+
+    fn holding(presale: &Presale, user: &Pubkey, program: &Pubkey, mut data: Vec<u8>) -> Result<Option<GateHolding>> {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let info = AccountInfo::new(&key, false, false, &mut lamports, &mut data, program, false, 0);
+        presale.gate_holding(user, &[info])
+    }
+
+    #[test]
+    fn mint_gate_scales_the_allocation_with_the_holding() {
+        let user = Pubkey::new_unique();
+        let gate_mint = Pubkey::new_unique();
+        let mut presale = Presale {
+            gate_mint,
+            gate_decimals: 2,
+            gate_sol_per_token: 1_000,
+            gate_max_allocation: 50_000,
+            ..Default::default()
+        };
+        assert!(holding(&presale, &user, &token::ID, token_account(gate_mint, user, 100)).unwrap().is_none());
+
+        presale.gate_mode = GateMode::Mint;
+        let held = holding(&presale, &user, &token::ID, token_account(gate_mint, user, 2_500)).unwrap().unwrap();
+        // the allocation is keyed by the wallet, whichever of its token accounts is passed
+        assert_eq!((held.key, held.allocation, held.allocation_index), (user, 25_000, 1));
+        let capped = holding(&presale, &user, &token_2022::ID, token_account(gate_mint, user, 10_000)).unwrap().unwrap();
+        assert_eq!((capped.key, capped.allocation), (user, 50_000));
+
+        let rejected = |data: Vec<u8>, program: &Pubkey| {
+            assert_eq!(holding(&presale, &user, program, data).err().unwrap(), PresaleError::NotTokenHolder.into());
+        };
+        rejected(token_account(gate_mint, user, 2_500), &Pubkey::new_unique());
+        rejected(token_account(gate_mint, Pubkey::new_unique(), 2_500), &token::ID);
+        rejected(token_account(Pubkey::new_unique(), user, 2_500), &token::ID);
+        rejected(token_account(gate_mint, user, 0), &token::ID);
+    }
+}
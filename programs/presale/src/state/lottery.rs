@@ -0,0 +1,258 @@
+use anchor_lang::prelude::*;
+use solana_program::hash::hashv;
+
+use crate::{constants::*, error::*, math::{bps_of, mul_div, SafeMath}, state::SettledAllocation};
+
+/// Lottery sale: buyers register tickets at a fixed price and `winning_tickets`
+/// of them are drawn from a seed the owner committed to before registration closed,
+/// mixed with the hash of `draw_slot`. That slot is only set once registration
+/// closed, so the owner can't know the outcome when committing to the seed.
+///
+/// Once revealed, ticket `i` wins when `(i * stride + offset) % ticket_count < winner_count`.
+/// `stride` is coprime with `ticket_count`, so the map is a permutation of the
+/// tickets and exactly `winner_count` of them win. Anyone can recompute
+/// `stride` and `offset` from `hash(seed, slot_hash, ticket_count)`.
+///
+/// The owner posts a bond when starting the lottery, returned once the winners
+/// are drawn. Holding back the seed to wait for another draw slot costs part of
+/// it, and never revealing costs all of it; the slashed bond is shared between
+/// the tickets.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct Lottery {
+    pub token_per_ticket: u64, // tokens a winning ticket gets, 0 when the sale isn't a lottery
+    pub sol_per_ticket: u64, // lamports paid per ticket, refunded to losing tickets
+    pub winning_tickets: u64, // tickets drawn at most
+    pub registration_end: i64, // end of the ticket registration
+    pub seed_hash: [u8; 32], // hash of the seed committed by the owner
+    pub ticket_count: u64, // tickets registered by all users
+    pub seed: [u8; 32], // revealed seed
+    pub draw_slot: u64, // slot whose hash is mixed into the draw, set once the registration closed
+    pub slot_hash: [u8; 32], // hash of `draw_slot`
+    pub stride: u64, // winner selection parameters derived from the seed
+    pub offset: u64,
+    pub winner_count: u64, // tickets drawn, min(winning_tickets, ticket_count)
+    pub sol_raised: u64, // lamports kept by the sale net of the platform fee, once revealed
+    pub revealed: bool, // the winners were drawn and can be claimed
+    pub bond: u64, // lamports posted by the owner and not slashed, returned once revealed
+    pub slashed_bond: u64, // slashed lamports not paid to the tickets yet
+    pub paid_tickets: u64, // tickets paid their share of the slashed bond
+}
+
+impl Lottery {
+    pub fn is_active(&self) -> bool {
+        self.token_per_ticket != 0
+    }
+
+    pub fn is_seed(&self, seed: &[u8; 32]) -> bool {
+        hashv(&[seed]).to_bytes() == self.seed_hash
+    }
+
+    /// The hash of `draw_slot`, from the `SlotHashes` sysvar. It only lists
+    /// recent slots, so it is gone when the seed isn't revealed soon after the
+    /// draw slot.
+    pub fn draw_slot_hash(&self, slot_hashes: &AccountInfo) -> Result<Option<[u8; 32]>> {
+        require!(self.draw_slot != 0, PresaleError::RegistrationOpen);
+        require!(Clock::get()?.slot > self.draw_slot, PresaleError::DrawSlotPending);
+
+        // a length followed by (slot, hash) entries, most recent slot first
+        let data = slot_hashes.try_borrow_data()?;
+        let read_u64 = |at: usize| -> Result<u64> {
+            let bytes = data.get(at..at + 8).ok_or(PresaleError::RevealExpired)?;
+            Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let entry_count = read_u64(0)? as usize;
+        for i in 0..entry_count {
+            let at = 8 + i * 40;
+            let slot = read_u64(at)?;
+            if slot == self.draw_slot {
+                let hash = data.get(at + 8..at + 40).ok_or(PresaleError::RevealExpired)?;
+                return Ok(Some(hash.try_into().unwrap()));
+            }
+            if slot < self.draw_slot {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Moves an expired draw slot `LOTTERY_DRAW_DELAY` slots ahead of `slot`,
+    /// for `LOTTERY_REARM_PENALTY_BPS` of the bond.
+    pub fn rearm(&mut self, slot: u64) -> Result<()> {
+        self.slash(bps_of(self.bond, LOTTERY_REARM_PENALTY_BPS)?)?;
+        self.draw_slot = slot.safe_add(LOTTERY_DRAW_DELAY)?;
+        Ok(())
+    }
+
+    /// Moves `amount` of the owner's bond to the ticket holders.
+    pub fn slash(&mut self, amount: u64) -> Result<()> {
+        self.bond = self.bond.safe_sub(amount)?;
+        self.slashed_bond = self.slashed_bond.safe_add(amount)?;
+        Ok(())
+    }
+
+    /// Pays `ticket_count` tickets their share of the slashed bond, the last
+    /// tickets get what the rounding left.
+    pub fn pay_bond_share(&mut self, ticket_count: u64) -> Result<u64> {
+        let unpaid_tickets = self.ticket_count.safe_sub(self.paid_tickets)?;
+        let share = mul_div(self.slashed_bond, ticket_count, unpaid_tickets)?;
+        self.slashed_bond = self.slashed_bond.safe_sub(share)?;
+        self.paid_tickets = self.paid_tickets.safe_add(ticket_count)?;
+        Ok(share)
+    }
+
+    /// Draws the winners from the revealed seed and the hash of the draw slot.
+    pub fn draw(&mut self, seed: [u8; 32], slot_hash: [u8; 32]) -> Result<()> {
+        let ticket_count = self.ticket_count.max(1);
+        let random = hashv(&[&seed, &slot_hash, &self.ticket_count.to_le_bytes()]).to_bytes();
+        let offset = u64::from_le_bytes(random[..8].try_into().unwrap()) % ticket_count;
+        let mut stride = (u64::from_le_bytes(random[8..16].try_into().unwrap()) % ticket_count).max(1);
+        while gcd(stride, ticket_count) != 1 {
+            stride = stride.safe_add(1)?;
+        }
+
+        self.seed = seed;
+        self.slot_hash = slot_hash;
+        self.stride = stride;
+        self.offset = offset;
+        self.winner_count = self.winning_tickets.min(self.ticket_count);
+        self.revealed = true;
+        Ok(())
+    }
+
+    pub fn is_winner(&self, ticket: u64) -> Result<bool> {
+        let position = (ticket as u128)
+            .safe_mul(self.stride as u128)?
+            .safe_add(self.offset as u128)?
+            .checked_rem(self.ticket_count as u128)
+            .ok_or(PresaleError::MathOverflow)?;
+        Ok(position < self.winner_count as u128)
+    }
+
+    /// A buyer's share of the drawn lottery, for `ticket_count` tickets starting at `first_ticket`.
+    pub fn allocation(&self, first_ticket: u64, ticket_count: u64) -> Result<SettledAllocation> {
+        let mut wins = 0u64;
+        for ticket in first_ticket..first_ticket.safe_add(ticket_count)? {
+            if self.is_winner(ticket)? {
+                wins = wins.safe_add(1)?;
+            }
+        }
+
+        Ok(SettledAllocation {
+            token_amount: wins.safe_mul(self.token_per_ticket)?,
+            sol_amount: mul_div(wins, self.sol_raised, self.winner_count)?,
+            excess_sol: ticket_count.safe_sub(wins)?.safe_mul(self.sol_per_ticket)?,
+        })
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{stub_syscalls, SLOT};
+
+    fn registered(ticket_count: u64, winning_tickets: u64) -> Lottery {
+        Lottery {
+            token_per_ticket: 100,
+            sol_per_ticket: 10,
+            winning_tickets,
+            ticket_count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn draw_picks_exactly_the_winning_tickets() {
+        for ticket_count in [1, 2, 7, 12, 64] {
+            let mut lottery = registered(ticket_count, 5);
+            lottery.draw([3; 32], [9; 32]).unwrap();
+            assert_eq!(lottery.winner_count, ticket_count.min(5));
+
+            // every ticket lands on its own position, so exactly `winner_count` of them win
+            let mut positions: Vec<u64> = (0..ticket_count)
+                .map(|ticket| (ticket * lottery.stride + lottery.offset) % ticket_count)
+                .collect();
+            positions.sort_unstable();
+            assert_eq!(positions, (0..ticket_count).collect::<Vec<_>>());
+            let wins = (0..ticket_count).filter(|&ticket| lottery.is_winner(ticket).unwrap()).count() as u64;
+            assert_eq!(wins, lottery.winner_count);
+        }
+    }
+
+    #[test]
+    fn draw_is_reproducible_from_the_seed_and_slot_hash() {
+        let mut lottery = Lottery { seed_hash: hashv(&[&[1; 32]]).to_bytes(), ..registered(1_000, 10) };
+        assert!(lottery.is_seed(&[1; 32]));
+        assert!(!lottery.is_seed(&[2; 32]));
+        let mut again = lottery;
+        lottery.draw([1; 32], [2; 32]).unwrap();
+        again.draw([1; 32], [2; 32]).unwrap();
+        assert_eq!((lottery.stride, lottery.offset), (again.stride, again.offset));
+        assert!(lottery.revealed);
+
+        // the owner can't steer the draw without the slot hash
+        let mut other_slot = registered(1_000, 10);
+        other_slot.draw([1; 32], [3; 32]).unwrap();
+        assert_ne!((lottery.stride, lottery.offset), (other_slot.stride, other_slot.offset));
+
+        // a buyer's allocation counts the tickets drawn among theirs
+        lottery.sol_raised = 100;
+        let allocation = lottery.allocation(0, 1_000).unwrap();
+        assert_eq!(allocation, SettledAllocation { token_amount: 1_000, sol_amount: 100, excess_sol: 9_900 });
+    }
+
+    fn slot_hashes(entries: &[(u64, [u8; 32])]) -> Vec<u8> {
+        let mut data = (entries.len() as u64).to_le_bytes().to_vec();
+        for (slot, hash) in entries {
+            data.extend_from_slice(&slot.to_le_bytes());
+            data.extend_from_slice(hash);
+        }
+        data
+    }
+
+    fn draw_slot_hash(lottery: &Lottery, mut data: Vec<u8>) -> Result<Option<[u8; 32]>> {
+        let key = solana_program::sysvar::slot_hashes::ID;
+        let mut lamports = 0;
+        let info = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &solana_program::sysvar::ID, false, 0);
+        lottery.draw_slot_hash(&info)
+    }
+
+    #[test]
+    fn draw_slot_hash_is_read_while_the_slot_is_recent() {
+        stub_syscalls();
+        let recent = slot_hashes(&[(SLOT - 1, [1; 32]), (SLOT - 2, [2; 32]), (SLOT - 4, [4; 32])]);
+        let mut lottery = registered(10, 5);
+        assert_eq!(draw_slot_hash(&lottery, recent.clone()).unwrap_err(), PresaleError::RegistrationOpen.into());
+
+        lottery.draw_slot = SLOT;
+        assert_eq!(draw_slot_hash(&lottery, recent.clone()).unwrap_err(), PresaleError::DrawSlotPending.into());
+
+        lottery.draw_slot = SLOT - 2;
+        assert_eq!(draw_slot_hash(&lottery, recent.clone()).unwrap(), Some([2; 32]));
+        // a skipped slot, or one that fell off the sysvar, can't be drawn from anymore
+        lottery.draw_slot = SLOT - 3;
+        assert_eq!(draw_slot_hash(&lottery, recent.clone()).unwrap(), None);
+        lottery.draw_slot = SLOT - 500;
+        assert_eq!(draw_slot_hash(&lottery, recent).unwrap(), None);
+    }
+
+    #[test]
+    fn rearming_slashes_the_bond_for_the_tickets() {
+        let mut lottery = Lottery { bond: 1_000, ..registered(3, 2) };
+        lottery.rearm(5_000).unwrap();
+        assert_eq!(lottery.draw_slot, 5_000 + LOTTERY_DRAW_DELAY);
+        assert_eq!((lottery.bond, lottery.slashed_bond), (750, 250));
+
+        // each ticket gets a third, the last one the rounding dust
+        assert_eq!(lottery.pay_bond_share(1).unwrap(), 83);
+        assert_eq!(lottery.pay_bond_share(1).unwrap(), 83);
+        assert_eq!(lottery.pay_bond_share(1).unwrap(), 84);
+        assert_eq!(lottery.slashed_bond, 0);
+    }
+}
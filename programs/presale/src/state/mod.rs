@@ -1,3 +1,19 @@
+pub mod auction;
+pub mod gate;
+pub mod lottery;
+pub mod platform;
 pub mod presale;
+pub mod referral;
+pub mod vote;
+pub mod voucher;
+pub mod whitelist;
 
-pub use presale::*;
\ No newline at end of file
+pub use auction::*;
+pub use gate::*;
+pub use lottery::*;
+pub use platform::*;
+pub use presale::*;
+pub use referral::*;
+pub use vote::*;
+pub use voucher::*;
+pub use whitelist::*;
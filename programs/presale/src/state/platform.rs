@@ -0,0 +1,47 @@
+use anchor_lang::prelude::*;
+
+use crate::{constants::*, error::*, math::bps_of};
+
+#[account]
+#[derive(Default)]
+pub struct Platform {
+    pub authority: Pubkey, // Launchpad operator, distinct from the presale owner
+    pub fee_vault: Pubkey, // Sol vault collecting the platform fee
+    pub fee_bps: u64, // fee taken from every token_sale payment, in basis points
+    pub total_fee_collected: u64, // total fee paid into the fee vault
+}
+
+impl Platform {
+    pub fn set_fee(&mut self, fee_bps: u64) -> Result<()> {
+        require!(fee_bps <= BASIS_POINTS, PresaleError::InvalidFee);
+        self.fee_bps = fee_bps;
+        Ok(())
+    }
+
+    /// The platform fee taken out of `sol_amount`, rounded down.
+    pub fn fee_on(&self, sol_amount: u64) -> Result<u64> {
+        bps_of(sol_amount, self.fee_bps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_is_taken_in_basis_points() {
+        let mut platform = Platform::default();
+        assert_eq!(platform.fee_on(1_000).unwrap(), 0);
+
+        platform.set_fee(250).unwrap();
+        assert_eq!(platform.fee_on(1_000).unwrap(), 25);
+        // the launchpad never rounds the fee up
+        assert_eq!(platform.fee_on(39).unwrap(), 0);
+        assert_eq!(platform.fee_on(u64::MAX).unwrap(), u64::MAX / 40);
+
+        assert_eq!(platform.set_fee(BASIS_POINTS + 1).unwrap_err(), PresaleError::InvalidFee.into());
+        assert_eq!(platform.fee_bps, 250);
+        platform.set_fee(BASIS_POINTS).unwrap();
+        assert_eq!(platform.fee_on(1_000).unwrap(), 1_000);
+    }
+}
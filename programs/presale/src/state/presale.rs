@@ -1,5 +1,12 @@
 use anchor_lang::prelude::*;
 
+use crate::{
+    constants::*,
+    error::*,
+    math::{bps_of, checked, mul_div, mul_div_ceil, to_u64, SafeMath},
+    state::{DutchAuction, GateMode, Lottery, ReferralReward},
+};
+
 #[account]
 #[derive(Default)]
 pub struct Presale {
@@ -7,17 +14,207 @@ pub struct Presale {
     pub vault: Pubkey, // Sol vault address of the contract
     pub token_vault: Pubkey, // Presale token address of the contract - escrow vault
     pub token: Pubkey, // Presale token address
-    pub token_amount: u64, // Token balance of the contract
+    pub token_amount: u64, // Token balance of the contract, or the remaining mintable supply in mint-on-demand mode
     pub sol_amount: u64, // Sol amount of the value on contract
     pub token_price: u64, // the token price will be expressed with sol
-    pub status: bool, // contract's status
-    pub sale_type: bool, // false: Private Sale, true: Public Sale
+    pub state: SaleState, // lifecycle state of the sale
     pub rate: u64, // token price increase rate
-    pub staked_amount: u64, // total token amount in staking
+    pub staked_amount: u64, // total token amount locked for outstanding stakes, yield included
     pub rate_3m: u64, // the yield rate for 3 months period
     pub rate_6m: u64, // the yield rate for 3 months period
     pub rate_9m: u64, // the yield rate for 3 months period
     pub rate_12m: u64, // the yield rate for 3 months period
+    pub unsold_token_mode: UnsoldTokenMode, // what finalize does with the unsold tokens
+    pub total_sol_raised: u64, // total sol paid by buyers
+    pub total_token_sold: u64, // total token amount sold, staked or not
+    pub refund_sol_amount: u64, // sol shared pro-rata between the buyers of a cancelled sale
+    pub release_schedule: ReleaseSchedule, // how the raised sol unlocks to the owner, fixed at init
+    pub ended_at: i64, // timestamp the sale moved to Ended, start of the release schedule
+    pub sol_withdrawn: u64, // raised sol already withdrawn by the owner
+    pub released_bps: u64, // share of the raised sol released by contributor votes
+    pub tranche_index: u8, // next tranche to vote on
+    pub vote_end: i64, // end of the running tranche vote, 0 when no vote is open
+    pub votes_for: u64, // contribution weight approving the running tranche
+    pub votes_against: u64, // contribution weight rejecting the running tranche
+    pub recipients: [Recipient; MAX_RECIPIENTS], // revenue split of the raised sol, unused entries are zeroed
+    pub referral_bps: u64, // referrer's share of a referred purchase, in basis points
+    pub referral_reward: ReferralReward, // whether referrers earn sol or tokens
+    pub referral_token_amount: u64, // token rewards locked in the vault for referrers
+    pub started_at: i64, // timestamp the sale left Draft, start of the early-bird windows
+    pub early_bird_bonus: [BonusWindow; MAX_BONUS_WINDOWS], // bonus tokens for early buyers, unused entries are zeroed
+    pub volume_bonus: [VolumeTier; MAX_VOLUME_TIERS], // bonus tokens for large buyers, unused entries are zeroed
+    pub voucher_signer: Pubkey, // off-chain key signing purchase vouchers, default disables vouchers
+    pub gate_mode: GateMode, // who can buy in the private sale
+    pub gate_mint: Pubkey, // mint, or collection mint, the private buyers have to hold
+    pub gate_decimals: u8, // decimals of the gate mint
+    pub gate_sol_per_token: u64, // private sale allocation in lamports per whole gate token held
+    pub gate_max_allocation: u64, // private sale allocation cap per holder in lamports
+    pub overflow_token_amount: u64, // tokens shared between the committers, 0 when the sale isn't an overflow sale
+    pub overflow_sol_target: u64, // lamports the overflow sale raises at most, commitments above it are refunded
+    pub overflow_end: i64, // end of the commitment window
+    pub total_committed: u64, // lamports committed by all users
+    pub overflow_sol_raised: u64, // committed lamports kept by the sale, net of the platform fee
+    pub overflow_settled: bool, // the commitments were settled and can be claimed
+    pub auction: DutchAuction, // dutch auction sale, replaces the public price curve when active
+    pub lottery: Lottery, // lottery sale, replaces the public price curve when active
+    pub whitelist_root: [u8; 32], // merkle root of the private sale whitelist, zeroed when the private sale is open to all
+    pub guaranteed_end: i64, // end of the guaranteed allocation round, the FCFS round follows
+    pub private_sol_cap: u64, // lamports the whitelisted private sale raises at most, fees included
+    pub private_sol_sold: u64, // lamports paid in the private sale so far, fees included
+    pub mint_on_demand: bool, // the presale mints the tokens it sells instead of holding an inventory
+    pub max_supply: u64, // tokens the presale can have minted and outstanding at most
+    pub minted_amount: u64, // tokens minted by the presale and not burned back
+    pub reserved_token_amount: u64, // tokens set aside for overflow, auction or lottery buyers and not sent yet
+    pub reserved_sol_amount: u64, // lamports of commitments, bids or tickets not settled or refunded yet
+    pub donated_token_amount: u64, // tokens found in the vault beyond the books while minting on demand, the inventory takes them otherwise
+    pub donated_sol_amount: u64, // lamports found in the vault beyond the books and not withdrawn, kept apart from sol_amount
+    pub migrated: bool, // migrated from the status flags layout, its earlier buyers have no contribution records
+    pub open_claims: u64, // overflow committers or auction bidders who didn't claim nor refund yet
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum SaleState {
+    /// Initialized, sales are not opened yet.
+    #[default]
+    Draft,
+    /// Private sale at the fixed price, purchased tokens are staked.
+    Private,
+    /// Public sale on the price curve, tokens go straight to the buyer.
+    Public,
+    /// Sales are halted and can resume into either phase.
+    Paused,
+    /// Sales are over and the presale waits to be finalized.
+    Ended,
+    /// The sale was aborted.
+    Cancelled,
+    /// The sale is settled for good.
+    Finalized,
+}
+
+impl SaleState {
+    /// The single source of truth for the lifecycle transitions.
+    pub fn can_transition_to(self, next: SaleState) -> bool {
+        use SaleState::*;
+
+        matches!(
+            (self, next),
+            (Draft, Private | Public | Cancelled)
+                | (Private, Public | Paused | Ended | Cancelled)
+                | (Public, Private | Paused | Ended | Cancelled)
+                | (Paused, Private | Public | Ended | Cancelled)
+                | (Ended, Finalized | Cancelled)
+        )
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum UnsoldTokenMode {
+    /// Burn the unsold tokens.
+    Burn,
+    /// Send the unsold tokens back to the owner. In mint-on-demand mode the
+    /// unsold supply is simply never minted.
+    #[default]
+    ReturnToOwner,
+    /// Keep the unsold tokens in the vault to fund staking rewards. The owner
+    /// can still withdraw them with `withdraw_token` after finalize.
+    KeepForRewards,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum ReleaseMode {
+    /// The raised sol can be withdrawn at any time.
+    #[default]
+    Immediate,
+    /// The raised sol unlocks linearly over `duration` seconds after the sale ends.
+    Linear,
+    /// The raised sol unlocks in steps given by `milestones`.
+    Milestones,
+    /// Each of the `milestones` is a tranche released by a contributor vote
+    /// lasting `duration` seconds, opened no earlier than its `unlock_delay`.
+    Voted,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Milestone {
+    pub unlock_delay: i64, // seconds after the sale ends
+    pub unlock_bps: u64, // cumulative share of the raised sol unlocked, in basis points
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct ReleaseSchedule {
+    pub mode: ReleaseMode,
+    pub duration: i64, // linear release duration, or tranche voting window, in seconds
+    pub milestones: [Milestone; MAX_MILESTONES], // unused entries are left zeroed
+}
+
+impl ReleaseSchedule {
+    pub fn validate(&self) -> Result<()> {
+        match self.mode {
+            ReleaseMode::Immediate => {},
+            ReleaseMode::Linear => {
+                require!(self.duration > 0, PresaleError::InvalidReleaseSchedule);
+            },
+            ReleaseMode::Milestones | ReleaseMode::Voted => {
+                if self.mode == ReleaseMode::Voted {
+                    require!(self.duration > 0, PresaleError::InvalidReleaseSchedule);
+                }
+                let mut last = Milestone::default();
+                for milestone in self.milestones.iter().filter(|m| m.unlock_bps != 0) {
+                    require!(milestone.unlock_delay >= last.unlock_delay, PresaleError::InvalidReleaseSchedule);
+                    require!(milestone.unlock_bps > last.unlock_bps, PresaleError::InvalidReleaseSchedule);
+                    last = *milestone;
+                }
+                // everything has to unlock eventually
+                require!(last.unlock_bps == BASIS_POINTS, PresaleError::InvalidReleaseSchedule);
+            },
+        }
+        Ok(())
+    }
+
+    /// The `index`-th milestone in use, if any.
+    pub fn tranche(&self, index: u8) -> Option<Milestone> {
+        self.milestones
+            .iter()
+            .filter(|m| m.unlock_bps != 0)
+            .nth(index.into())
+            .copied()
+    }
+
+    /// Share of the raised sol unlocked `elapsed` seconds after the sale ended, in basis points.
+    /// Voted tranches are tracked on the presale and not covered here.
+    pub fn unlocked_bps(&self, elapsed: i64) -> Result<u64> {
+        Ok(match self.mode {
+            ReleaseMode::Immediate => BASIS_POINTS,
+            ReleaseMode::Linear => {
+                mul_div(to_u64(elapsed.clamp(0, self.duration))?, BASIS_POINTS, to_u64(self.duration)?)?
+            },
+            ReleaseMode::Voted => 0,
+            ReleaseMode::Milestones => self.milestones
+                .iter()
+                .filter(|m| m.unlock_bps != 0 && m.unlock_delay <= elapsed)
+                .map(|m| m.unlock_bps)
+                .max()
+                .unwrap_or(0),
+        })
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Recipient {
+    pub wallet: Pubkey, // receiver of the share
+    pub share_bps: u64, // share of every distribution, in basis points
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct BonusWindow {
+    pub end_offset: i64, // the window closes this many seconds after the sale started
+    pub bonus_bps: u64, // bonus tokens on top of the purchase, in basis points
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct VolumeTier {
+    pub min_sol_paid: u64, // cumulative lamports a user has to pay to reach the tier
+    pub bonus_bps: u64, // bonus on all tokens bought by the user, in basis points
 }
 
 #[account]
@@ -40,4 +237,863 @@ pub struct UserInfo {
     pub stake_amount_12m: u64,      // Stake amount for 12 months
     pub stake_start_time_12m: i64,  // Start time for 12-month stake
     pub stake_status_12m: bool,   // Status for 12-month stake
+
+    // fields are only ever appended, `migrate_user_info` grows older accounts
+
+    pub sol_contributed: u64,       // Sol paid for all purchases
+    pub token_received: u64,        // Tokens sent to the user, public purchases and claimed stakes
+
+    pub referrer: Pubkey,           // Referrer account credited for the user's purchases
+    pub is_referrer: bool,          // The user owns a referral code and can't be referred
+
+    pub sol_paid: u64,              // Lamports paid for all purchases, fees included
+    pub volume_token_amount: u64,   // Tokens bought before any bonus
+    pub volume_bonus_paid: u64,     // Volume bonus tokens already granted
+
+    pub private_sol_paid: u64,      // Lamports paid in the private sale, fees included
+    pub committed: u64,             // Lamports committed to the overflow sale and not claimed yet
+    pub auction_token_amount: u64,  // Tokens bid for in the dutch auction and not claimed yet
+    pub auction_sol_paid: u64,      // Lamports paid for those bids
+    pub lottery_first_ticket: u64,  // First lottery ticket registered by the user
+    pub lottery_ticket_count: u64,  // Lottery tickets registered and not claimed yet
+    pub whitelisted: bool,          // The user proved their whitelist entry
+    pub whitelist_allocation: u64,  // Lamports guaranteed to the user in the first private round
+    pub whitelist_root: [u8; 32],   // Whitelist root the user's entry was proved against
+}
+
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PurchaseMode {
+    /// Spend exactly `amount` lamports. The token amount is rounded down and
+    /// any rounding dust stays in the vault.
+    ExactIn,
+    /// Receive exactly `amount` tokens. The lamport cost is rounded up.
+    ExactOut,
+}
+
+/// A user's share of a settled overflow sale or auction.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct SettledAllocation {
+    pub token_amount: u64, // tokens sent to the user
+    pub sol_amount: u64, // lamports kept by the sale, net of the platform fee
+    pub excess_sol: u64, // lamports sent back to the user
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, Debug)]
+pub struct PurchaseQuote {
+    pub token_amount: u64, // tokens bought, before any bonus
+    pub sol_amount: u64, // lamports paid by the buyer
+    pub token_price: u64, // token price after the trade
+    pub bonus_amount: u64, // early-bird bonus tokens on top of the purchase
+    pub volume_bonus_amount: u64, // volume bonus tokens granted with the purchase
+}
+
+impl Presale {
+    /// Fails unless the sale is in one of the `allowed` states.
+    pub fn require_state(&self, allowed: &[SaleState]) -> Result<()> {
+        require!(allowed.contains(&self.state), PresaleError::InvalidSaleState);
+        Ok(())
+    }
+
+    pub fn transition_to(&mut self, next: SaleState) -> Result<()> {
+        require!(self.state.can_transition_to(next), PresaleError::InvalidStateTransition);
+        msg!("sale state {:?} -> {:?}", self.state, next);
+        let current_timestamp = Clock::get()?.unix_timestamp;
+        if self.state == SaleState::Draft && (next == SaleState::Private || next == SaleState::Public) {
+            self.started_at = current_timestamp;
+        }
+        if next == SaleState::Ended {
+            self.ended_at = current_timestamp;
+        }
+        self.state = next;
+        Ok(())
+    }
+
+    /// Early-bird bonus of a purchase at `now`, in basis points.
+    pub fn early_bird_bonus_bps(&self, now: i64) -> Result<u64> {
+        let elapsed = now.safe_sub(self.started_at)?;
+
+        Ok(self.early_bird_bonus
+            .iter()
+            .filter(|w| w.bonus_bps != 0)
+            .find(|w| elapsed < w.end_offset)
+            .map_or(0, |w| w.bonus_bps))
+    }
+
+    /// Volume bonus of a user who paid `sol_paid` lamports in total, in basis points.
+    pub fn volume_bonus_bps(&self, sol_paid: u64) -> u64 {
+        self.volume_bonus
+            .iter()
+            .filter(|t| t.bonus_bps != 0 && t.min_sol_paid <= sol_paid)
+            .map(|t| t.bonus_bps)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The configured revenue split, an empty split means the owner withdraws everything.
+    pub fn active_recipients(&self) -> impl Iterator<Item = &Recipient> {
+        self.recipients.iter().filter(|r| r.wallet != Pubkey::default())
+    }
+
+    /// The referrer's reward on a purchase, in sol or in tokens: the sol and
+    /// token amounts it takes out of the presale's share.
+    pub fn referral_split(&self, sol_amount: u64, token_amount: u64) -> Result<(u64, u64)> {
+        match self.referral_reward {
+            ReferralReward::Sol => Ok((bps_of(sol_amount, self.referral_bps)?, 0)),
+            ReferralReward::Token => Ok((0, bps_of(token_amount, self.referral_bps)?)),
+        }
+    }
+
+    /// Either no split at all or shares adding up to the whole.
+    pub fn set_recipients(&mut self, recipients: [Recipient; MAX_RECIPIENTS]) -> Result<()> {
+        let active = recipients.iter().filter(|r| r.wallet != Pubkey::default());
+        let total_bps = active.clone().try_fold(0u64, |total, r| total.safe_add(r.share_bps))?;
+        require!(
+            active.count() == 0 || total_bps == BASIS_POINTS,
+            PresaleError::InvalidRecipients
+        );
+        self.recipients = recipients;
+        Ok(())
+    }
+
+    /// Splits `amount` between the active recipients, in the configured order.
+    pub fn revenue_shares(&self, amount: u64) -> Result<Vec<(Pubkey, u64)>> {
+        let recipients: Vec<&Recipient> = self.active_recipients().collect();
+        require!(!recipients.is_empty(), PresaleError::InvalidRecipients);
+
+        let mut shares = Vec::with_capacity(recipients.len());
+        let mut distributed = 0u64;
+        for (i, recipient) in recipients.iter().enumerate() {
+            // the last recipient takes the rounding dust
+            let share = if i == recipients.len() - 1 {
+                amount.safe_sub(distributed)?
+            } else {
+                bps_of(amount, recipient.share_bps)?
+            };
+            distributed = distributed.safe_add(share)?;
+            shares.push((recipient.wallet, share));
+        }
+        Ok(shares)
+    }
+
+    /// Cancels the sale and freezes the sol left in the vault for pro-rata refunds.
+    /// A migrated sale can't be cancelled, the sol of its earlier buyers couldn't
+    /// be refunded nor withdrawn anymore.
+    pub fn cancel(&mut self) -> Result<()> {
+        require!(!self.migrated, PresaleError::MigratedSale);
+        self.transition_to(SaleState::Cancelled)?;
+        self.freeze_for_refunds()
+    }
+
+    /// The refund of a buyer who contributed `sol_contributed` lamports to the
+    /// cancelled sale: their pro-rata share of the sol frozen in the vault,
+    /// less the share of the `token_bought` tokens they kept.
+    pub fn refund_amount(&self, sol_contributed: u64, token_bought: u64, token_kept: u64) -> Result<u64> {
+        if sol_contributed == 0 {
+            return Ok(0);
+        }
+        let share = mul_div(sol_contributed, self.refund_sol_amount, self.total_sol_raised)?;
+        if token_bought == 0 {
+            return Ok(share);
+        }
+        mul_div(share, token_bought.safe_sub(token_kept)?, token_bought)
+    }
+
+    /// Cancels the sale after its contributors rejected a tranche. This is the
+    /// only way out of `Finalized`, the owner can't cancel a finalized sale.
+    pub fn reject_tranche(&mut self) -> Result<()> {
+        if self.state == SaleState::Finalized {
+            msg!("sale state {:?} -> {:?}", self.state, SaleState::Cancelled);
+            self.state = SaleState::Cancelled;
+        } else {
+            self.transition_to(SaleState::Cancelled)?;
+        }
+        self.freeze_for_refunds()
+    }
+
+    /// Closes the running tranche vote. Either side needs a majority of a
+    /// quorum of the raised sol, silence approves nothing.
+    pub fn settle_tranche_vote(&mut self) -> Result<()> {
+        self.vote_end = 0;
+        msg!("votes for {:?}, votes against {:?}", self.votes_for, self.votes_against);
+
+        let turnout = self.votes_for.safe_add(self.votes_against)?;
+        let quorum = bps_of(self.total_sol_raised, TRANCHE_VOTE_QUORUM_BPS)?;
+
+        if turnout < quorum || self.votes_for == self.votes_against {
+            // the tranche stays locked until a new vote decides it
+            msg!("no decision on tranche {:?}", self.tranche_index);
+            Ok(())
+        } else if self.votes_for > self.votes_against {
+            // release the tranche to the owner
+            let tranche = self.release_schedule.tranche(self.tranche_index).ok_or(PresaleError::NoTrancheLeft)?;
+            self.released_bps = tranche.unlock_bps;
+            self.tranche_index = self.tranche_index.safe_add(1)?;
+            Ok(())
+        } else {
+            // the contributors rejected the tranche, refund the remaining escrow
+            self.reject_tranche()
+        }
+    }
+
+    fn freeze_for_refunds(&mut self) -> Result<()> {
+        self.end_unrevealed_lottery()?;
+        self.refund_sol_amount = self.sol_amount;
+
+        // staked tokens will never be claimed, put them back to the inventory
+        self.token_amount = self.token_amount.safe_add(self.staked_amount)?;
+        self.staked_amount = 0;
+
+        // so are the referral token rewards
+        self.token_amount = self.token_amount.safe_add(self.referral_token_amount)?;
+        self.referral_token_amount = 0;
+
+        // so is the allocation of an overflow sale that never settled
+        if !self.overflow_settled {
+            self.release_tokens(self.overflow_token_amount)?;
+            self.overflow_token_amount = 0;
+        }
+        if self.auction.is_active() && !self.auction.settled {
+            self.release_tokens(self.auction.token_amount)?;
+            self.auction.token_amount = 0;
+        }
+        Ok(())
+    }
+
+    /// Ends a lottery whose winners were never drawn: the winners' tokens go
+    /// back to the inventory and the sale is no longer a lottery. The tickets
+    /// stay refundable and share the owner's whole bond.
+    pub fn end_unrevealed_lottery(&mut self) -> Result<()> {
+        if self.lottery.is_active() && !self.lottery.revealed {
+            if self.lottery.ticket_count != 0 {
+                self.lottery.slash(self.lottery.bond)?;
+            } else {
+                // only a cancel ends a lottery without tickets, the bond is refunded with the vault
+                self.sol_amount = self.sol_amount.safe_add(self.lottery.bond)?;
+                self.lottery.bond = 0;
+            }
+            self.release_tokens(self.lottery.winning_tickets.safe_mul(self.lottery.token_per_ticket)?)?;
+            self.lottery.winning_tickets = 0;
+            self.lottery.token_per_ticket = 0;
+        }
+        Ok(())
+    }
+
+    /// Records a committer or bidder opening a claim on the overflow sale or the auction.
+    pub fn open_claim(&mut self) -> Result<()> {
+        self.open_claims = self.open_claims.safe_add(1)?;
+        Ok(())
+    }
+
+    /// Records a claim or refund of the overflow sale or the auction. After the
+    /// last one, the rounding dust of the allocations is all that is left in
+    /// reserve: its tokens go back to the inventory and its lamports are raised,
+    /// or refunded with the rest of a cancelled sale.
+    pub fn close_claim(&mut self) -> Result<()> {
+        self.open_claims = self.open_claims.safe_sub(1)?;
+        if self.open_claims != 0 {
+            return Ok(());
+        }
+
+        let sol_dust = self.reserved_sol_amount;
+        msg!("settlement dust {:?} tokens, {:?} lamports", self.reserved_token_amount, sol_dust);
+        self.release_tokens(self.reserved_token_amount)?;
+        self.reserved_sol_amount = 0;
+        self.sol_amount = self.sol_amount.safe_add(sol_dust)?;
+        if self.state == SaleState::Cancelled {
+            self.refund_sol_amount = self.refund_sol_amount.safe_add(sol_dust)?;
+        } else {
+            self.total_sol_raised = self.total_sol_raised.safe_add(sol_dust)?;
+        }
+        Ok(())
+    }
+
+    // puts tokens reserved for a sale mode back to the inventory
+    fn release_tokens(&mut self, amount: u64) -> Result<()> {
+        self.token_amount = self.token_amount.safe_add(amount)?;
+        self.reserved_token_amount = self.reserved_token_amount.safe_sub(amount)?;
+        Ok(())
+    }
+
+    /// Tokens the token vault should hold according to the books.
+    pub fn expected_token_balance(&self) -> Result<u64> {
+        // minted tokens go straight to the buyers, only donations sit in the vault
+        if self.mint_on_demand {
+            return Ok(self.donated_token_amount);
+        }
+        self.token_amount
+            .safe_add(self.staked_amount)?
+            .safe_add(self.referral_token_amount)?
+            .safe_add(self.reserved_token_amount)
+    }
+
+    /// Lamports the sol vault should hold according to the books.
+    pub fn expected_sol_balance(&self) -> Result<u64> {
+        self.sol_amount
+            .safe_add(self.reserved_sol_amount)?
+            .safe_add(self.donated_sol_amount)?
+            .safe_add(self.lottery.bond)?
+            .safe_add(self.lottery.slashed_bond)
+    }
+
+    /// Corrects the books to the actual vault balances.
+    pub fn reconcile_balances(&mut self, token_actual: u64, sol_actual: u64) -> Result<()> {
+        let token_expected = self.expected_token_balance()?;
+        let sol_expected = self.expected_sol_balance()?;
+
+        // a surplus was sent to the vault directly, it joins the inventory,
+        // or the donations withdrawable with withdraw_token when minting on demand
+        if token_actual > token_expected {
+            let surplus = token_actual.safe_sub(token_expected)?;
+            if self.mint_on_demand {
+                self.donated_token_amount = self.donated_token_amount.safe_add(surplus)?;
+            } else {
+                self.token_amount = self.token_amount.safe_add(surplus)?;
+            }
+        } else if self.mint_on_demand {
+            let shortfall = token_expected.safe_sub(token_actual)?;
+            self.donated_token_amount = self.donated_token_amount.safe_sub(shortfall)?;
+        } else {
+            let shortfall = token_expected.safe_sub(token_actual)?.min(self.token_amount);
+            self.token_amount = self.token_amount.safe_sub(shortfall)?;
+        }
+
+        // sol donations are kept apart from the raised sol, withdrawable with withdraw_donated_sol
+        if sol_actual > sol_expected {
+            let surplus = sol_actual.safe_sub(sol_expected)?;
+            self.donated_sol_amount = self.donated_sol_amount.safe_add(surplus)?;
+        } else {
+            let shortfall = sol_expected.safe_sub(sol_actual)?.min(self.sol_amount);
+            self.sol_amount = self.sol_amount.safe_sub(shortfall)?;
+        }
+        Ok(())
+    }
+
+    /// Raised sol the owner can withdraw at `now` under the release schedule.
+    pub fn withdrawable_sol(&self, now: i64) -> Result<u64> {
+        if self.release_schedule.mode == ReleaseMode::Immediate {
+            return Ok(self.sol_amount);
+        }
+        // the schedule starts when the sale ends
+        if self.state != SaleState::Ended && self.state != SaleState::Finalized {
+            return Ok(0);
+        }
+
+        let unlocked_bps = if self.release_schedule.mode == ReleaseMode::Voted {
+            self.released_bps
+        } else {
+            self.release_schedule.unlocked_bps(now.safe_sub(self.ended_at)?)?
+        };
+        let unlocked = bps_of(self.total_sol_raised, unlocked_bps)?;
+        Ok(unlocked.saturating_sub(self.sol_withdrawn))
+    }
+
+    /// Fails until the overflow sale, the auction and the lottery, when there
+    /// is one, are settled and every buyer's contribution is known.
+    pub fn require_settled(&self) -> Result<()> {
+        require!(self.overflow_token_amount == 0 || self.overflow_settled, PresaleError::CommitmentOpen);
+        require!(!self.auction.is_active() || self.auction.settled, PresaleError::AuctionRunning);
+        require!(!self.lottery.is_active() || self.lottery.revealed, PresaleError::RegistrationOpen);
+        Ok(())
+    }
+
+    /// The sol a user contributed, including the settled overflow, auction
+    /// and lottery allocations the user didn't claim yet.
+    pub fn contribution_of(&self, user_info: &UserInfo) -> Result<u64> {
+        let mut contribution = user_info.sol_contributed;
+        if self.overflow_settled && user_info.committed != 0 {
+            contribution = contribution.safe_add(self.overflow_allocation(user_info.committed)?.sol_amount)?;
+        }
+        if self.auction.settled && user_info.auction_token_amount != 0 {
+            let allocation = self.auction.allocation(user_info.auction_token_amount, user_info.auction_sol_paid)?;
+            contribution = contribution.safe_add(allocation.sol_amount)?;
+        }
+        if self.lottery.revealed && user_info.lottery_ticket_count != 0 {
+            let allocation = self.lottery.allocation(user_info.lottery_first_ticket, user_info.lottery_ticket_count)?;
+            contribution = contribution.safe_add(allocation.sol_amount)?;
+        }
+        Ok(contribution)
+    }
+
+    /// Splits a commitment of a settled overflow sale pro-rata to the total committed.
+    pub fn overflow_allocation(&self, committed: u64) -> Result<SettledAllocation> {
+        let total_committed = self.total_committed;
+        // rounded up so the excess sent back never adds up to more than the vault holds
+        let sol_kept = mul_div_ceil(committed, self.overflow_sol_target.min(total_committed), total_committed)?;
+
+        Ok(SettledAllocation {
+            token_amount: mul_div(committed, self.overflow_token_amount, total_committed)?,
+            sol_amount: mul_div(committed, self.overflow_sol_raised, total_committed)?,
+            excess_sol: committed.safe_sub(sol_kept)?,
+        })
+    }
+
+    /// Prices a purchase in the given phase without touching the state.
+    ///
+    /// Private sale sells at the fixed `token_price`. Public sale bumps the
+    /// price by `rate * tokens / 2` and sells the whole amount at the bumped price.
+    pub fn quote(&self, sale_type: bool, mode: PurchaseMode, amount: u64, decimals: u8) -> Result<PurchaseQuote> {
+        let rate = if sale_type { self.rate } else { 0 };
+        quote_at(self.token_price, rate, mode, amount, decimals)
+    }
+
+    /// Prices a bid in the running dutch auction at `now`. Bids earn no bonus.
+    pub fn auction_quote(&self, mode: PurchaseMode, amount: u64, now: i64) -> Result<PurchaseQuote> {
+        quote_at(self.auction.price_at(now)?, 0, mode, amount, self.auction.decimals)
+    }
+
+    /// Adds the early-bird bonus of a purchase at `now` and the volume bonus
+    /// it unlocks for a user who bought `user_info` so far.
+    pub fn add_bonuses(&self, quote: &mut PurchaseQuote, user_info: &UserInfo, now: i64) -> Result<()> {
+        quote.bonus_amount = bps_of(quote.token_amount, self.early_bird_bonus_bps(now)?)?;
+
+        // the volume bonus is owed on everything the user bought so far, minus what was already granted
+        let sol_paid = user_info.sol_paid.safe_add(quote.sol_amount)?;
+        let volume_token_amount = user_info.volume_token_amount.safe_add(quote.token_amount)?;
+        let volume_bonus_owed = bps_of(volume_token_amount, self.volume_bonus_bps(sol_paid))?;
+        quote.volume_bonus_amount = volume_bonus_owed.saturating_sub(user_info.volume_bonus_paid);
+
+        Ok(())
+    }
+}
+
+/// Prices a purchase starting at `token_price` and moving by `rate`, see `Presale::quote`.
+pub fn quote_at(token_price: u64, rate: u64, mode: PurchaseMode, amount: u64, decimals: u8) -> Result<PurchaseQuote> {
+    let quote = checked(match mode {
+        PurchaseMode::ExactIn => presale_math::quote_exact_in(token_price, rate, amount, decimals),
+        PurchaseMode::ExactOut => presale_math::quote_exact_out(token_price, rate, amount, decimals),
+    })?;
+
+    Ok(PurchaseQuote {
+        token_amount: quote.token_amount,
+        sol_amount: quote.sol_amount,
+        token_price: quote.token_price,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::stub_syscalls;
+
+    #[test]
+    fn sale_state_transitions() {
+        use SaleState::*;
+
+        let states = [Draft, Private, Public, Paused, Ended, Cancelled, Finalized];
+        let allowed = [
+            (Draft, Private), (Draft, Public), (Draft, Cancelled),
+            (Private, Public), (Private, Paused), (Private, Ended), (Private, Cancelled),
+            (Public, Private), (Public, Paused), (Public, Ended), (Public, Cancelled),
+            (Paused, Private), (Paused, Public), (Paused, Ended), (Paused, Cancelled),
+            (Ended, Finalized), (Ended, Cancelled),
+        ];
+        for from in states {
+            for to in states {
+                assert_eq!(from.can_transition_to(to), allowed.contains(&(from, to)), "{:?} -> {:?}", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn require_state_checks_the_allowed_list() {
+        let presale = Presale { state: SaleState::Ended, ..Default::default() };
+
+        assert!(presale.require_state(&[SaleState::Ended, SaleState::Finalized]).is_ok());
+        assert_eq!(
+            presale.require_state(&[SaleState::Private, SaleState::Public]).unwrap_err(),
+            PresaleError::InvalidSaleState.into()
+        );
+        assert!(presale.require_state(&[]).is_err());
+    }
+
+    fn cancellable_sale() -> Presale {
+        Presale {
+            state: SaleState::Public,
+            token_amount: 1_000,
+            sol_amount: 900,
+            total_sol_raised: 1_000,
+            staked_amount: 100,
+            referral_token_amount: 10,
+            overflow_token_amount: 50,
+            reserved_token_amount: 50,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cancel_freezes_the_vault_for_refunds() {
+        stub_syscalls();
+        let mut presale = cancellable_sale();
+
+        presale.cancel().unwrap();
+        assert_eq!(presale.state, SaleState::Cancelled);
+        assert_eq!(presale.refund_sol_amount, 900);
+        // stakes, referral rewards and the unsettled overflow allocation go back to the inventory
+        assert_eq!(presale.token_amount, 1_160);
+        assert_eq!(presale.staked_amount, 0);
+        assert_eq!(presale.referral_token_amount, 0);
+        assert_eq!(presale.reserved_token_amount, 0);
+    }
+
+    #[test]
+    fn cancel_refuses_migrated_and_finalized_sales() {
+        stub_syscalls();
+        let mut migrated = Presale { migrated: true, ..cancellable_sale() };
+        assert_eq!(migrated.cancel().unwrap_err(), PresaleError::MigratedSale.into());
+        assert_eq!(migrated.state, SaleState::Public);
+
+        let mut finalized = Presale { state: SaleState::Finalized, ..cancellable_sale() };
+        assert_eq!(finalized.cancel().unwrap_err(), PresaleError::InvalidStateTransition.into());
+        // only a rejected tranche gets a finalized sale cancelled
+        finalized.reject_tranche().unwrap();
+        assert_eq!(finalized.state, SaleState::Cancelled);
+        assert_eq!(finalized.refund_sol_amount, 900);
+    }
+
+    #[test]
+    fn refund_shrinks_with_the_tokens_kept() {
+        let presale = Presale { refund_sol_amount: 900, total_sol_raised: 1_000, ..Default::default() };
+
+        // 100 lamports contributed out of 1000 raised, 90 left to refund
+        assert_eq!(presale.refund_amount(100, 0, 0).unwrap(), 90);
+        assert_eq!(presale.refund_amount(100, 40, 0).unwrap(), 90);
+        assert_eq!(presale.refund_amount(100, 40, 10).unwrap(), 67);
+        assert_eq!(presale.refund_amount(100, 40, 40).unwrap(), 0);
+        assert_eq!(presale.refund_amount(0, 40, 0).unwrap(), 0);
+        assert!(presale.refund_amount(100, 40, 41).is_err());
+    }
+
+    fn milestones(steps: &[(i64, u64)]) -> [Milestone; MAX_MILESTONES] {
+        let mut milestones = [Milestone::default(); MAX_MILESTONES];
+        for (milestone, &(unlock_delay, unlock_bps)) in milestones.iter_mut().zip(steps) {
+            *milestone = Milestone { unlock_delay, unlock_bps };
+        }
+        milestones
+    }
+
+    #[test]
+    fn release_schedule_validation() {
+        let linear = ReleaseSchedule { mode: ReleaseMode::Linear, duration: 0, ..Default::default() };
+        assert!(linear.validate().is_err());
+        assert!(ReleaseSchedule { duration: 100, ..linear }.validate().is_ok());
+
+        let steps = ReleaseSchedule {
+            mode: ReleaseMode::Milestones,
+            duration: 0,
+            milestones: milestones(&[(0, 2_500), (100, 10_000)]),
+        };
+        assert!(steps.validate().is_ok());
+        // a voted schedule needs a voting window
+        assert!(ReleaseSchedule { mode: ReleaseMode::Voted, ..steps }.validate().is_err());
+        // everything has to unlock, in order
+        assert!(ReleaseSchedule { milestones: milestones(&[(0, 2_500), (100, 9_000)]), ..steps }.validate().is_err());
+        assert!(ReleaseSchedule { milestones: milestones(&[(100, 2_500), (0, 10_000)]), ..steps }.validate().is_err());
+        assert!(ReleaseSchedule { milestones: milestones(&[(0, 5_000), (100, 5_000)]), ..steps }.validate().is_err());
+    }
+
+    #[test]
+    fn unlocked_bps_follows_the_schedule() {
+        let immediate = ReleaseSchedule::default();
+        assert_eq!(immediate.unlocked_bps(0).unwrap(), BASIS_POINTS);
+
+        let linear = ReleaseSchedule { mode: ReleaseMode::Linear, duration: 1_000, ..Default::default() };
+        assert_eq!(linear.unlocked_bps(-10).unwrap(), 0);
+        assert_eq!(linear.unlocked_bps(250).unwrap(), 2_500);
+        assert_eq!(linear.unlocked_bps(5_000).unwrap(), BASIS_POINTS);
+
+        let steps = ReleaseSchedule {
+            mode: ReleaseMode::Milestones,
+            duration: 0,
+            milestones: milestones(&[(100, 2_500), (200, 10_000)]),
+        };
+        assert_eq!(steps.unlocked_bps(99).unwrap(), 0);
+        assert_eq!(steps.unlocked_bps(100).unwrap(), 2_500);
+        assert_eq!(steps.unlocked_bps(200).unwrap(), BASIS_POINTS);
+
+        // voted tranches are released by the votes only
+        assert_eq!(ReleaseSchedule { mode: ReleaseMode::Voted, ..steps }.unlocked_bps(1_000).unwrap(), 0);
+        assert_eq!(steps.tranche(1), Some(Milestone { unlock_delay: 200, unlock_bps: 10_000 }));
+        assert_eq!(steps.tranche(2), None);
+    }
+
+    #[test]
+    fn withdrawable_sol_starts_when_the_sale_ends() {
+        let release_schedule = ReleaseSchedule { mode: ReleaseMode::Linear, duration: 1_000, ..Default::default() };
+        let mut presale = Presale {
+            state: SaleState::Public,
+            sol_amount: 800,
+            total_sol_raised: 1_000,
+            release_schedule,
+            ended_at: 10_000,
+            sol_withdrawn: 200,
+            ..Default::default()
+        };
+        assert_eq!(presale.withdrawable_sol(20_000).unwrap(), 0);
+
+        presale.state = SaleState::Ended;
+        assert_eq!(presale.withdrawable_sol(10_100).unwrap(), 0);
+        assert_eq!(presale.withdrawable_sol(10_500).unwrap(), 300);
+        assert_eq!(presale.withdrawable_sol(11_000).unwrap(), 800);
+
+        presale.release_schedule = ReleaseSchedule::default();
+        presale.state = SaleState::Public;
+        assert_eq!(presale.withdrawable_sol(0).unwrap(), 800);
+    }
+
+    fn voted_sale(votes_for: u64, votes_against: u64) -> Presale {
+        Presale {
+            state: SaleState::Ended,
+            sol_amount: 1_000,
+            total_sol_raised: 1_000,
+            release_schedule: ReleaseSchedule {
+                mode: ReleaseMode::Voted,
+                duration: 100,
+                milestones: milestones(&[(0, 5_000), (100, 10_000)]),
+            },
+            vote_end: 10_000,
+            votes_for,
+            votes_against,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tranche_vote_needs_a_majority_of_a_quorum() {
+        stub_syscalls();
+        // below the quorum nothing is decided, even a unanimous vote
+        let mut presale = voted_sale(99, 0);
+        presale.settle_tranche_vote().unwrap();
+        assert_eq!((presale.vote_end, presale.tranche_index, presale.released_bps), (0, 0, 0));
+        assert_eq!(presale.state, SaleState::Ended);
+
+        // a tie decides nothing either
+        let mut presale = voted_sale(60, 60);
+        presale.settle_tranche_vote().unwrap();
+        assert_eq!((presale.tranche_index, presale.released_bps), (0, 0));
+
+        let mut presale = voted_sale(70, 50);
+        presale.settle_tranche_vote().unwrap();
+        assert_eq!((presale.tranche_index, presale.released_bps), (1, 5_000));
+        assert_eq!(presale.withdrawable_sol(0).unwrap(), 500);
+
+        let mut presale = voted_sale(50, 70);
+        presale.settle_tranche_vote().unwrap();
+        assert_eq!(presale.state, SaleState::Cancelled);
+        assert_eq!(presale.refund_sol_amount, 1_000);
+    }
+
+    #[test]
+    fn vote_weight_counts_the_settled_allocations() {
+        let user_info = UserInfo {
+            sol_contributed: 100,
+            committed: 300,
+            auction_token_amount: 10,
+            auction_sol_paid: 50,
+            ..Default::default()
+        };
+        let mut presale = Presale {
+            overflow_token_amount: 1_000,
+            total_committed: 1_200,
+            overflow_sol_target: 600,
+            overflow_sol_raised: 600,
+            ..Default::default()
+        };
+        // unsettled commitments and bids don't weigh anything yet
+        assert_eq!(presale.contribution_of(&user_info).unwrap(), 100);
+        assert_eq!(presale.require_settled().unwrap_err(), PresaleError::CommitmentOpen.into());
+
+        presale.overflow_settled = true;
+        presale.require_settled().unwrap();
+        assert_eq!(presale.contribution_of(&user_info).unwrap(), 250);
+
+        presale.auction = DutchAuction {
+            token_amount: 100,
+            decimals: 0,
+            token_sold: 20,
+            clearing_price: 4,
+            sol_raised: 80,
+            ..Default::default()
+        };
+        assert_eq!(presale.require_settled().unwrap_err(), PresaleError::AuctionRunning.into());
+        presale.auction.settled = true;
+        presale.require_settled().unwrap();
+        assert_eq!(presale.contribution_of(&user_info).unwrap(), 290);
+    }
+
+    #[test]
+    fn recipient_shares_add_up_to_the_whole() {
+        let recipient = |n: u8, share_bps| Recipient { wallet: Pubkey::new_from_array([n; 32]), share_bps };
+        let mut presale = Presale::default();
+        assert_eq!(presale.revenue_shares(100).unwrap_err(), PresaleError::InvalidRecipients.into());
+
+        let mut recipients = [Recipient::default(); MAX_RECIPIENTS];
+        recipients[0] = recipient(1, 3_333);
+        recipients[1] = recipient(2, 3_333);
+        assert_eq!(presale.set_recipients(recipients).unwrap_err(), PresaleError::InvalidRecipients.into());
+        recipients[2] = recipient(3, 3_334);
+        presale.set_recipients(recipients).unwrap();
+
+        // the last recipient takes the rounding dust
+        let shares = presale.revenue_shares(1_001).unwrap();
+        assert_eq!(shares, vec![(recipient(1, 0).wallet, 333), (recipient(2, 0).wallet, 333), (recipient(3, 0).wallet, 335)]);
+
+        // an empty split hands everything back to the owner
+        presale.set_recipients([Recipient::default(); MAX_RECIPIENTS]).unwrap();
+        assert_eq!(presale.active_recipients().count(), 0);
+    }
+
+    #[test]
+    fn referral_reward_is_paid_in_sol_or_tokens() {
+        let mut presale = Presale { referral_bps: 500, ..Default::default() };
+        assert_eq!(presale.referral_split(1_000, 4_000).unwrap(), (50, 0));
+        presale.referral_reward = ReferralReward::Token;
+        assert_eq!(presale.referral_split(1_000, 4_000).unwrap(), (0, 200));
+    }
+
+    #[test]
+    fn early_bird_bonus_shrinks_with_time() {
+        let mut presale = Presale { started_at: 1_000, ..Default::default() };
+        presale.early_bird_bonus[0] = BonusWindow { end_offset: 86_400, bonus_bps: 1_000 };
+        presale.early_bird_bonus[1] = BonusWindow { end_offset: 3 * 86_400, bonus_bps: 500 };
+
+        assert_eq!(presale.early_bird_bonus_bps(1_000).unwrap(), 1_000);
+        assert_eq!(presale.early_bird_bonus_bps(1_000 + 86_399).unwrap(), 1_000);
+        assert_eq!(presale.early_bird_bonus_bps(1_000 + 86_400).unwrap(), 500);
+        assert_eq!(presale.early_bird_bonus_bps(1_000 + 3 * 86_400).unwrap(), 0);
+
+        let mut quote = PurchaseQuote { token_amount: 2_000, sol_amount: 10, ..Default::default() };
+        presale.add_bonuses(&mut quote, &UserInfo::default(), 1_000).unwrap();
+        assert_eq!((quote.bonus_amount, quote.volume_bonus_amount), (200, 0));
+    }
+
+    #[test]
+    fn volume_bonus_doesnt_depend_on_how_the_purchase_is_split() {
+        let mut presale = Presale::default();
+        presale.volume_bonus[0] = VolumeTier { min_sol_paid: 100, bonus_bps: 500 };
+        presale.volume_bonus[1] = VolumeTier { min_sol_paid: 500, bonus_bps: 1_000 };
+        assert_eq!(presale.volume_bonus_bps(99), 0);
+        assert_eq!(presale.volume_bonus_bps(100), 500);
+        assert_eq!(presale.volume_bonus_bps(10_000), 1_000);
+
+        // one purchase of 600 lamports for 6_000 tokens
+        let mut quote = PurchaseQuote { token_amount: 6_000, sol_amount: 600, ..Default::default() };
+        presale.add_bonuses(&mut quote, &UserInfo::default(), 0).unwrap();
+        assert_eq!(quote.volume_bonus_amount, 600);
+
+        // the same purchase in three parts, the user info recorded the earlier ones
+        let mut user_info = UserInfo::default();
+        let mut granted = 0;
+        for _ in 0..3 {
+            let mut quote = PurchaseQuote { token_amount: 2_000, sol_amount: 200, ..Default::default() };
+            presale.add_bonuses(&mut quote, &user_info, 0).unwrap();
+            user_info.sol_paid += quote.sol_amount;
+            user_info.volume_token_amount += quote.token_amount;
+            user_info.volume_bonus_paid += quote.volume_bonus_amount;
+            granted += quote.volume_bonus_amount;
+        }
+        assert_eq!(granted, 600);
+    }
+
+    #[test]
+    fn overflow_claims_release_the_rounding_dust() {
+        stub_syscalls();
+        let commitments = [100, 100, 101];
+        // settled: 200 of the 301 committed lamports raised, all 1_000 tokens sold
+        let mut presale = Presale {
+            state: SaleState::Ended,
+            overflow_token_amount: 1_000,
+            overflow_sol_target: 200,
+            total_committed: 301,
+            overflow_sol_raised: 200,
+            overflow_settled: true,
+            reserved_token_amount: 1_000,
+            reserved_sol_amount: 101,
+            sol_amount: 200,
+            total_sol_raised: 200,
+            open_claims: 3,
+            ..Default::default()
+        };
+
+        let allocations: Vec<SettledAllocation> = commitments
+            .iter()
+            .map(|&committed| presale.overflow_allocation(committed).unwrap())
+            .collect();
+        assert_eq!(allocations[0], SettledAllocation { token_amount: 332, sol_amount: 66, excess_sol: 33 });
+        assert_eq!(allocations[2], SettledAllocation { token_amount: 335, sol_amount: 67, excess_sol: 33 });
+
+        for allocation in &allocations {
+            presale.reserved_token_amount -= allocation.token_amount;
+            presale.reserved_sol_amount -= allocation.excess_sol;
+            presale.close_claim().unwrap();
+        }
+        // the last claim puts the dust back to the inventory and the raised sol
+        assert_eq!((presale.reserved_token_amount, presale.reserved_sol_amount), (0, 0));
+        assert_eq!(presale.token_amount, 1);
+        assert_eq!((presale.sol_amount, presale.total_sol_raised), (202, 202));
+    }
+
+    #[test]
+    fn cancel_slashes_the_bond_of_an_unrevealed_lottery() {
+        stub_syscalls();
+        let lottery = Lottery {
+            token_per_ticket: 100,
+            sol_per_ticket: 10,
+            winning_tickets: 2,
+            bond: 500,
+            ..Default::default()
+        };
+        let sale = Presale {
+            state: SaleState::Public,
+            token_amount: 800,
+            reserved_token_amount: 200,
+            sol_amount: 0,
+            reserved_sol_amount: 30,
+            lottery,
+            ..Default::default()
+        };
+
+        // the registered tickets share the whole bond
+        let mut presale = Presale { lottery: Lottery { ticket_count: 3, ..lottery }, ..sale };
+        presale.cancel().unwrap();
+        assert_eq!((presale.lottery.bond, presale.lottery.slashed_bond), (0, 500));
+        assert_eq!((presale.token_amount, presale.reserved_token_amount), (1_000, 0));
+        assert!(!presale.lottery.is_active());
+        assert_eq!(presale.expected_sol_balance().unwrap(), 530);
+
+        // without tickets the bond is refunded with the rest of the vault
+        let mut presale = sale;
+        presale.cancel().unwrap();
+        assert_eq!((presale.lottery.bond, presale.lottery.slashed_bond), (0, 0));
+        assert_eq!((presale.sol_amount, presale.refund_sol_amount), (500, 500));
+    }
+
+    #[test]
+    fn reconcile_books_the_surplus_as_donations() {
+        let mut presale = Presale {
+            token_amount: 1_000,
+            staked_amount: 200,
+            sol_amount: 500,
+            reserved_sol_amount: 100,
+            ..Default::default()
+        };
+        presale.reconcile_balances(1_250, 650).unwrap();
+        // tokens sent to the vault join the inventory, sol is kept apart from the raised sol
+        assert_eq!((presale.token_amount, presale.donated_token_amount), (1_050, 0));
+        assert_eq!((presale.sol_amount, presale.donated_sol_amount), (500, 50));
+        assert_eq!(presale.expected_token_balance().unwrap(), 1_250);
+        assert_eq!(presale.expected_sol_balance().unwrap(), 650);
+
+        // a shortfall comes out of the inventory and the raised sol
+        presale.reconcile_balances(1_200, 600).unwrap();
+        assert_eq!((presale.token_amount, presale.sol_amount), (1_000, 450));
+
+        // when minting on demand the vault only holds donations
+        let mut presale = Presale { mint_on_demand: true, token_amount: 9_000, ..Default::default() };
+        presale.reconcile_balances(300, 0).unwrap();
+        assert_eq!((presale.token_amount, presale.donated_token_amount), (9_000, 300));
+        presale.reconcile_balances(100, 0).unwrap();
+        assert_eq!(presale.donated_token_amount, 100);
+    }
 }
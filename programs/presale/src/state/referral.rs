@@ -0,0 +1,68 @@
+use anchor_lang::prelude::*;
+
+use crate::{error::*, state::UserInfo};
+
+#[account]
+#[derive(Default)]
+pub struct Referrer {
+    pub owner: Pubkey, // Wallet earning the referral rewards
+    pub code: [u8; 8], // Referral code, seed of this account
+    pub referral_count: u64, // Number of purchases referred
+    pub referred_sol: u64, // Total sol paid by the referred buyers
+    pub total_sol_earned: u64, // Total sol rewards, claimed or not
+    pub total_token_earned: u64, // Total token rewards, claimed or not
+    pub sol_claimable: u64, // Sol rewards held by this account until claimed
+    pub token_claimable: u64, // Token rewards locked in the token vault until claimed
+}
+
+impl Referrer {
+    /// Rejects self-referrals, referral chains and a second referrer for the
+    /// same user. `key` is the address of this account.
+    pub fn check_referral(&self, key: &Pubkey, user: &Pubkey, user_info: &UserInfo) -> Result<()> {
+        require!(self.owner != *user, PresaleError::SelfReferral);
+        require!(!user_info.is_referrer, PresaleError::ReferralChain);
+        require!(
+            user_info.referrer == Pubkey::default() || user_info.referrer == *key,
+            PresaleError::InvalidReferrer
+        );
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum ReferralReward {
+    /// The referrer earns a share of the sol paid.
+    #[default]
+    Sol,
+    /// The referrer earns a share of the tokens bought.
+    Token,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn referrals_are_one_level_deep_and_final() {
+        let user = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let referrer = Referrer { owner: Pubkey::new_unique(), ..Default::default() };
+        let mut user_info = UserInfo::default();
+        referrer.check_referral(&key, &user, &user_info).unwrap();
+
+        let own = Referrer { owner: user, ..Default::default() };
+        assert_eq!(own.check_referral(&key, &user, &user_info).unwrap_err(), PresaleError::SelfReferral.into());
+
+        // a referred user keeps the first referrer
+        user_info.referrer = key;
+        referrer.check_referral(&key, &user, &user_info).unwrap();
+        assert_eq!(
+            referrer.check_referral(&Pubkey::new_unique(), &user, &user_info).unwrap_err(),
+            PresaleError::InvalidReferrer.into()
+        );
+
+        // a referrer can't be referred in turn
+        user_info.is_referrer = true;
+        assert_eq!(referrer.check_referral(&key, &user, &user_info).unwrap_err(), PresaleError::ReferralChain.into());
+    }
+}
@@ -0,0 +1,10 @@
+use anchor_lang::prelude::*;
+
+#[account]
+#[derive(Default)]
+pub struct TrancheVote {
+    pub user: Pubkey, // Contributor who voted
+    pub tranche: u8, // Index of the tranche voted on
+    pub approve: bool, // true: release the tranche, false: refund the escrow
+    pub weight: u64, // Sol contributed by the voter, settled allocations included
+}
@@ -0,0 +1,141 @@
+use anchor_lang::prelude::*;
+
+use crate::error::*;
+use solana_program::{
+    ed25519_program,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked}
+};
+
+/// Off-chain authorization for a purchase at a negotiated price, signed by
+/// the presale's `voucher_signer`. The signed message is the program id and
+/// the presale address followed by the borsh encoding of the voucher, so a
+/// voucher can't be redeemed against another deployment or presale.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Voucher {
+    pub buyer: Pubkey, // Only this wallet can redeem the voucher
+    pub max_amount: u64, // Max lamports paid with the voucher
+    pub price: u64, // Negotiated token price, replaces the sale price
+    pub expiry: i64, // Unix timestamp after which the voucher is void
+    pub nonce: u64, // Unique per voucher, its nonce account blocks replays
+}
+
+#[account]
+#[derive(Default)]
+pub struct VoucherNonce {
+    pub buyer: Pubkey, // Wallet that redeemed the voucher
+    pub nonce: u64,
+}
+
+impl Voucher {
+    /// Checks that the instruction right before the current one is an ed25519
+    /// program instruction verifying `signer`'s signature over this voucher
+    /// for `presale`.
+    pub fn verify(&self, instructions: &AccountInfo, signer: &Pubkey, presale: &Pubkey) -> Result<()> {
+        let current_index = load_current_index_checked(instructions)?;
+        require!(current_index > 0, PresaleError::InvalidVoucher);
+
+        let ix = load_instruction_at_checked(current_index as usize - 1, instructions)?;
+        require!(ix.program_id == ed25519_program::ID, PresaleError::InvalidVoucher);
+
+        // a single signature whose key, signature and message all live in that instruction
+        let data = &ix.data;
+        let read_u16 = |at: usize| -> Result<usize> {
+            let bytes = data.get(at..at + 2).ok_or(PresaleError::InvalidVoucher)?;
+            Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+        };
+        require!(data.first() == Some(&1), PresaleError::InvalidVoucher);
+        let this_instruction = u16::MAX as usize;
+        require!(read_u16(4)? == this_instruction, PresaleError::InvalidVoucher);
+        require!(read_u16(8)? == this_instruction, PresaleError::InvalidVoucher);
+        require!(read_u16(14)? == this_instruction, PresaleError::InvalidVoucher);
+
+        let public_key_offset = read_u16(6)?;
+        let public_key = data
+            .get(public_key_offset..public_key_offset + 32)
+            .ok_or(PresaleError::InvalidVoucher)?;
+        require!(public_key == signer.as_ref(), PresaleError::InvalidVoucher);
+
+        let message_offset = read_u16(10)?;
+        let message_size = read_u16(12)?;
+        let message = data
+            .get(message_offset..message_offset + message_size)
+            .ok_or(PresaleError::InvalidVoucher)?;
+        require!(message == self.message(presale)?.as_slice(), PresaleError::InvalidVoucher);
+
+        Ok(())
+    }
+
+    /// The bytes the voucher signer signs for `presale`.
+    pub fn message(&self, presale: &Pubkey) -> Result<Vec<u8>> {
+        let mut message = Vec::with_capacity(64 + std::mem::size_of::<Voucher>());
+        message.extend_from_slice(crate::ID.as_ref());
+        message.extend_from_slice(presale.as_ref());
+        self.serialize(&mut message)?;
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::sysvar::{self, instructions::{construct_instructions_data, store_current_index, BorrowedInstruction}};
+
+    // an ed25519 program instruction carrying one signature, key and message
+    fn ed25519_data(signer: &Pubkey, message: &[u8], instruction_index: u16) -> Vec<u8> {
+        let (public_key_offset, signature_offset, message_offset) = (16u16, 48u16, 112u16);
+        let mut data = vec![1, 0];
+        for field in [
+            signature_offset,
+            instruction_index,
+            public_key_offset,
+            instruction_index,
+            message_offset,
+            message.len() as u16,
+            instruction_index,
+        ] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(signer.as_ref());
+        // the signature itself is checked by the ed25519 program
+        data.extend_from_slice(&[7; 64]);
+        data.extend_from_slice(message);
+        data
+    }
+
+    fn verify(voucher: &Voucher, instructions: &[(Pubkey, Vec<u8>)], current: u16, signer: &Pubkey, presale: &Pubkey) -> Result<()> {
+        let borrowed: Vec<BorrowedInstruction> = instructions
+            .iter()
+            .map(|(program_id, data)| BorrowedInstruction { program_id, accounts: vec![], data })
+            .collect();
+        let mut data = construct_instructions_data(&borrowed);
+        store_current_index(&mut data, current);
+
+        let mut lamports = 0;
+        let info = AccountInfo::new(&sysvar::instructions::ID, false, false, &mut lamports, &mut data, &sysvar::ID, false, 0);
+        voucher.verify(&info, signer, presale)
+    }
+
+    #[test]
+    fn voucher_needs_the_signers_ed25519_instruction_right_before() {
+        let signer = Pubkey::new_unique();
+        let presale = Pubkey::new_unique();
+        let voucher = Voucher { buyer: Pubkey::new_unique(), max_amount: 1_000, price: 5, expiry: 100, nonce: 1 };
+        let message = voucher.message(&presale).unwrap();
+        let signed = (ed25519_program::ID, ed25519_data(&signer, &message, u16::MAX));
+        let token_sale = (crate::ID, vec![]);
+
+        verify(&voucher, &[signed.clone(), token_sale.clone()], 1, &signer, &presale).unwrap();
+
+        let invalid = |result: Result<()>| assert_eq!(result.unwrap_err(), PresaleError::InvalidVoucher.into());
+        // signed by someone else
+        invalid(verify(&voucher, &[signed.clone(), token_sale.clone()], 1, &Pubkey::new_unique(), &presale));
+        // for another presale, or another voucher
+        invalid(verify(&voucher, &[signed.clone(), token_sale.clone()], 1, &signer, &Pubkey::new_unique()));
+        invalid(verify(&Voucher { price: 4, ..voucher }, &[signed.clone(), token_sale.clone()], 1, &signer, &presale));
+        // no signature before the purchase
+        invalid(verify(&voucher, &[token_sale.clone(), signed], 0, &signer, &presale));
+        // a key and message read from another instruction of the transaction
+        let borrowed = (ed25519_program::ID, ed25519_data(&signer, &message, 1));
+        invalid(verify(&voucher, &[borrowed, token_sale], 1, &signer, &presale));
+    }
+}
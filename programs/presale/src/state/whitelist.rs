@@ -0,0 +1,108 @@
+use anchor_lang::prelude::*;
+use solana_program::hash::hashv;
+
+use crate::{error::*, math::SafeMath, state::{Presale, UserInfo}};
+
+/// Leaf of the whitelist merkle tree: the wallet and its guaranteed allocation
+/// in lamports, 0 for wallets only allowed in the first-come-first-served round.
+pub fn whitelist_leaf(user: &Pubkey, allocation: u64) -> [u8; 32] {
+    hashv(&[user.as_ref(), &allocation.to_le_bytes()]).to_bytes()
+}
+
+/// Checks a merkle proof, pairs are hashed in sorted order.
+pub fn verify_proof(proof: &[[u8; 32]], root: &[u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            hashv(&[&node, sibling]).to_bytes()
+        } else {
+            hashv(&[sibling, &node]).to_bytes()
+        }
+    });
+    computed == *root
+}
+
+impl Presale {
+    pub fn has_whitelist(&self) -> bool {
+        self.whitelist_root != [0; 32]
+    }
+
+    /// Checks a private purchase of `sol_amount` lamports against the two
+    /// whitelist rounds. Until `guaranteed_end` each wallet is limited to its
+    /// guaranteed allocation; afterwards whatever is left of `private_sol_cap`
+    /// is open to every whitelisted wallet. Entries proved against an older
+    /// root have to be proved again.
+    pub fn check_whitelist(&self, user_info: &UserInfo, sol_amount: u64, now: i64) -> Result<()> {
+        require!(user_info.whitelisted, PresaleError::NotWhitelisted);
+        require!(user_info.whitelist_root == self.whitelist_root, PresaleError::NotWhitelisted);
+        require!(self.private_sol_sold.safe_add(sol_amount)? <= self.private_sol_cap, PresaleError::AllocationExceeded);
+
+        if now < self.guaranteed_end {
+            require!(
+                user_info.private_sol_paid.safe_add(sol_amount)? <= user_info.whitelist_allocation,
+                PresaleError::AllocationExceeded
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_pair(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+        if a <= b { hashv(&[&a, &b]).to_bytes() } else { hashv(&[&b, &a]).to_bytes() }
+    }
+
+    #[test]
+    fn proofs_check_the_wallet_and_its_allocation() {
+        let wallets: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let leaves: Vec<[u8; 32]> = wallets.iter().zip([500, 0, 200]).map(|(w, a)| whitelist_leaf(w, a)).collect();
+        // three leaves, the odd one is paired with the root of the other two
+        let node = hash_pair(leaves[0], leaves[1]);
+        let root = hash_pair(node, leaves[2]);
+
+        assert!(verify_proof(&[leaves[1], leaves[2]], &root, leaves[0]));
+        assert!(verify_proof(&[leaves[0], leaves[2]], &root, leaves[1]));
+        assert!(verify_proof(&[node], &root, leaves[2]));
+
+        // a larger allocation, another wallet or a wrong path don't prove anything
+        assert!(!verify_proof(&[leaves[1], leaves[2]], &root, whitelist_leaf(&wallets[0], 501)));
+        assert!(!verify_proof(&[node], &root, whitelist_leaf(&Pubkey::new_unique(), 200)));
+        assert!(!verify_proof(&[leaves[2]], &root, leaves[0]));
+        assert!(!verify_proof(&[], &root, leaves[0]));
+    }
+
+    #[test]
+    fn guaranteed_round_then_fcfs_round() {
+        let root = [5; 32];
+        let presale = Presale {
+            whitelist_root: root,
+            private_sol_cap: 1_000,
+            private_sol_sold: 600,
+            guaranteed_end: 100,
+            ..Default::default()
+        };
+        let user_info = UserInfo {
+            whitelisted: true,
+            whitelist_allocation: 300,
+            whitelist_root: root,
+            private_sol_paid: 200,
+            ..Default::default()
+        };
+        assert!(presale.has_whitelist());
+
+        presale.check_whitelist(&user_info, 100, 99).unwrap();
+        assert_eq!(presale.check_whitelist(&user_info, 101, 99).unwrap_err(), PresaleError::AllocationExceeded.into());
+        // afterwards only the cap is left
+        presale.check_whitelist(&user_info, 400, 100).unwrap();
+        assert_eq!(presale.check_whitelist(&user_info, 401, 100).unwrap_err(), PresaleError::AllocationExceeded.into());
+
+        let not_proved = UserInfo { whitelisted: false, ..user_info };
+        assert_eq!(presale.check_whitelist(&not_proved, 1, 100).unwrap_err(), PresaleError::NotWhitelisted.into());
+        // an entry proved against a replaced root has to be proved again
+        let stale = Presale { whitelist_root: [6; 32], ..presale };
+        assert_eq!(stale.check_whitelist(&user_info, 1, 100).unwrap_err(), PresaleError::NotWhitelisted.into());
+    }
+}
@@ -0,0 +1,94 @@
+//! Syscall stubs for the unit tests, which run outside of the runtime.
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    token::spl_token::state::{Account as TokenAccountState, AccountState, Mint as MintState},
+    token_2022::spl_token_2022::instruction::TokenInstruction,
+};
+use solana_program::{
+    entrypoint::{ProgramResult, SUCCESS},
+    instruction::Instruction,
+    program_pack::Pack,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+};
+use std::{cell::{Cell, RefCell}, sync::Once};
+
+/// Unix timestamp `Clock::get` returns in the tests.
+pub const NOW: i64 = 1_700_000_000;
+/// Slot `Clock::get` returns in the tests.
+pub const SLOT: u64 = 1_000;
+
+thread_local! {
+    static TRANSFER_FEE_BPS: Cell<u64> = const { Cell::new(0) };
+    static INVOKED_ACCOUNTS: RefCell<Vec<Pubkey>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Makes the emulated token program withhold `fee_bps` of every transfer
+/// from the destination, like a transfer-fee mint, for the calling test.
+pub fn set_transfer_fee_bps(fee_bps: u64) {
+    TRANSFER_FEE_BPS.with(|fee| fee.set(fee_bps));
+}
+
+/// Accounts of the last instruction the calling test invoked.
+pub fn invoked_accounts() -> Vec<Pubkey> {
+    INVOKED_ACCOUNTS.with(|accounts| accounts.borrow().clone())
+}
+
+struct Stubs;
+
+impl SyscallStubs for Stubs {
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock { slot: SLOT, unix_timestamp: NOW, ..Default::default() };
+        unsafe { *(var_addr as *mut Clock) = clock };
+        SUCCESS
+    }
+
+    // emulates the token program instructions the presale invokes
+    fn sol_invoke_signed(&self, instruction: &Instruction, account_infos: &[AccountInfo], _signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+        let account = |index: usize| {
+            let key = instruction.accounts[index].pubkey;
+            account_infos.iter().find(|info| info.key == &key).ok_or(ProgramError::NotEnoughAccountKeys)
+        };
+        INVOKED_ACCOUNTS.with(|accounts| *accounts.borrow_mut() = instruction.accounts.iter().map(|meta| meta.pubkey).collect());
+        match TokenInstruction::unpack(&instruction.data)? {
+            TokenInstruction::TransferChecked { amount, .. } => {
+                let fee = amount * TRANSFER_FEE_BPS.with(Cell::get) / 10_000;
+                add_balance(account(0)?, -(amount as i128))?;
+                add_balance(account(2)?, (amount - fee) as i128)
+            },
+            TokenInstruction::MintTo { amount } => add_balance(account(1)?, amount as i128),
+            TokenInstruction::Burn { amount } => add_balance(account(0)?, -(amount as i128)),
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+}
+
+fn add_balance(token_account: &AccountInfo, amount: i128) -> ProgramResult {
+    let mut data = token_account.try_borrow_mut_data()?;
+    let mut state = TokenAccountState::unpack(&data)?;
+    state.amount = u64::try_from(state.amount as i128 + amount).map_err(|_| ProgramError::InsufficientFunds)?;
+    state.pack_into_slice(&mut data);
+    Ok(())
+}
+
+/// Installs the stubs, once for all the tests of the process.
+pub fn stub_syscalls() {
+    static STUBS: Once = Once::new();
+    STUBS.call_once(|| {
+        set_syscall_stubs(Box::new(Stubs));
+    });
+}
+
+/// Data of a token account holding `amount` tokens of `mint`.
+pub fn token_account_data(mint: Pubkey, owner: Pubkey, amount: u64) -> Vec<u8> {
+    let mut data = vec![0; TokenAccountState::LEN];
+    TokenAccountState { mint, owner, amount, state: AccountState::Initialized, ..Default::default() }.pack_into_slice(&mut data);
+    data
+}
+
+/// Data of a mint whose tokens `mint_authority` can mint.
+pub fn mint_data(mint_authority: Pubkey, decimals: u8) -> Vec<u8> {
+    let mut data = vec![0; MintState::LEN];
+    MintState { mint_authority: Some(mint_authority).into(), decimals, is_initialized: true, ..Default::default() }.pack_into_slice(&mut data);
+    data
+}
@@ -13,8 +13,8 @@ describe("presale", () => {
 
   const program = anchor.workspace.Presale as Program<Presale>;
 
-  let presale, vault, tokenVault: PublicKey;
-  let presaleBump, vaultBump, tokenVaultBump: Number;
+  let presale, vault, tokenVault, platform, feeVault: PublicKey;
+  let presaleBump, vaultBump, tokenVaultBump, platformBump, feeVaultBump: Number;
   let tokenMint = new PublicKey("9aXG6LBZWhSapmjQDfuZzBp67Nd3TDbdrJKi1yDZVDCp");
 
   // Bmed1qoe6u8VxmJ5p6SW77fb7LiSqWmQdTtKTz5dyh62
@@ -45,14 +45,64 @@ describe("presale", () => {
       ],
       program.programId
     );
+
+    [platform, platformBump] = await anchor.web3.PublicKey.findProgramAddress(
+      [
+        Buffer.from("PLATFORM_SEED")
+      ],
+      program.programId
+    );
+
+    [feeVault, feeVaultBump] = await anchor.web3.PublicKey.findProgramAddress(
+      [
+        Buffer.from("FEE_VAULT_SEED")
+      ],
+      program.programId
+    );
     // const presaleData = await program.account.presale.fetch(presale);
     // const tokenAmount = presaleData.tokenAmount;
     // console.log(presaleData);
   });
+  it("initialize the platform", async () => {
+    try {
+      let feeBps = 200; // 2% of every purchase
+      // the owner has to be the upgrade authority of the program
+      const [programData] = PublicKey.findProgramAddressSync(
+        [program.programId.toBuffer()],
+        new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+      );
+
+      const tx = await program.rpc.initializePlatform(
+        new anchor.BN(feeBps), {
+          accounts: {
+            authority: owner.publicKey,
+            platform,
+            feeVault,
+            program: program.programId,
+            programData,
+            systemProgram: SystemProgram.programId
+          },
+          signers: [owner]
+        }
+      );
+      console.log("Your transaction signature", tx);
+    } catch (error) {
+      console.log(error);
+    }
+  });
   it("Is initialized!", async () => {
     try {
       let amount = 10000000000000;
       let tokenPrice = 100000;
+      // raised sol can be withdrawn at any time
+      const releaseSchedule = {
+        mode: { immediate: {} },
+        duration: new anchor.BN(0),
+        milestones: Array(5).fill({
+          unlockDelay: new anchor.BN(0),
+          unlockBps: new anchor.BN(0)
+        })
+      };
 
       const tokenAccount = await getAssociatedTokenAddress(
         tokenMint,
@@ -61,7 +111,9 @@ describe("presale", () => {
       // Add your test here.
       const tx = await program.rpc.initialize(
         new anchor.BN(amount),
-        new anchor.BN(tokenPrice), {
+        new anchor.BN(tokenPrice),
+        { returnToOwner: {} },
+        releaseSchedule, {
           accounts: {
             owner: owner.publicKey,
             presale,
@@ -82,8 +134,8 @@ describe("presale", () => {
   });
   it("active the contract", async() => {
     try {
-      const tx = await program.rpc.toggleStatus(
-        {
+      const tx = await program.rpc.updateSaleState(
+        { private: {} }, {
           accounts: {
             owner: owner.publicKey,
             presale
@@ -115,17 +167,29 @@ describe("presale", () => {
         program.programId
       )
 
+      const maxSolAmount = amount;
+      const minTokenAmount = 0;
+
       const tx = await program.rpc.tokenSale(
+        { exactIn: {} },
         new anchor.BN(amount),
-        staked_period, {
+        staked_period,
+        new anchor.BN(maxSolAmount),
+        new anchor.BN(minTokenAmount),
+        null, {
           accounts: {
             user: user.publicKey,
             userInfo,
             presale,
             vault,
+            platform,
+            feeVault,
+            referrer: null,
             tokenMint,
             tokenAccount:userTokenAccount,
             tokenVaultAccount: tokenVault,
+            voucherNonce: null,
+            instructions: null,
             tokenProgram:TOKEN_PROGRAM_ID,
             associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
             systemProgram: SystemProgram.programId
@@ -181,8 +245,8 @@ describe("presale", () => {
 
   it("convert sale into public", async() => {
     try {
-      const tx = await program.rpc.updateSaleType(
-        {
+      const tx = await program.rpc.updateSaleState(
+        { public: {} }, {
           accounts: {
             owner: owner.publicKey,
             presale
@@ -213,6 +277,24 @@ describe("presale", () => {
       console.log(error);
     }
   });
+  it("quote token sale for public", async() => {
+    try {
+      let amount = 10000000000; // 100 token in public sale
+      const quote = await program.methods
+        .quote({ public: {} }, { exactOut: {} }, new anchor.BN(amount))
+        .accounts({
+          presale,
+          tokenMint,
+          userInfo: null
+        })
+        .view();
+      console.log("token amount->", quote.tokenAmount.toString());
+      console.log("sol amount->", quote.solAmount.toString());
+      console.log("token price->", quote.tokenPrice.toString());
+    } catch (error) {
+      console.log(error);
+    }
+  });
   it("token sale for public", async() => {
     try {
       let amount = 10000000000; // 100 token in public sale
@@ -230,18 +312,29 @@ describe("presale", () => {
         program.programId
       );
       const stakingPeriod = 0;
+      const maxSolAmount = 2000000000; // revert if 100 token cost more than 2 sol
+      const minTokenAmount = amount;
 
       const tx = await program.rpc.tokenSale(
+        { exactOut: {} },
         new anchor.BN(amount), 
-        stakingPeriod,{
+        stakingPeriod,
+        new anchor.BN(maxSolAmount),
+        new anchor.BN(minTokenAmount),
+        null, {
           accounts: {
             user: user.publicKey,
             presale,
             userInfo,
             vault,
+            platform,
+            feeVault,
+            referrer: null,
             tokenMint,
             tokenAccount:userTokenAccount,
             tokenVaultAccount: tokenVault,
+            voucherNonce: null,
+            instructions: null,
             tokenProgram:TOKEN_PROGRAM_ID,
             associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
             systemProgram: SystemProgram.programId
@@ -308,4 +401,47 @@ describe("presale", () => {
       console.log(error);
     }
   });
+  it("end the sale", async() => {
+    try {
+      const tx = await program.rpc.updateSaleState(
+        { ended: {} }, {
+          accounts: {
+            owner: owner.publicKey,
+            presale
+          },
+          signers: [owner]
+        }
+      );
+      console.log("tx->", tx);
+    } catch (error) {
+      console.log(error);
+    }
+  });
+  it("finalize the sale", async() => {
+    try {
+      const tokenAccount = await getAssociatedTokenAddress(
+        tokenMint,
+        owner.publicKey
+      );
+
+      const tx = await program.rpc.finalize(
+        {
+          accounts: {
+            owner: owner.publicKey,
+            presale,
+            tokenMint,
+            tokenAccount,
+            tokenVaultAccount:tokenVault,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+            systemProgram: SystemProgram.programId
+          },
+          signers: [owner]
+        }
+      );
+      console.log("tx->", tx);
+    } catch (error) {
+      console.log(error);
+    }
+  });
 });